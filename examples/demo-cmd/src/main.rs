@@ -20,6 +20,7 @@ async fn main() {
         client_sdk_key,
         refresh_interval,
         start_wait,
+        ..Default::default()
     };
 
     let user = FPUser::new("uniq_key");
@@ -0,0 +1,143 @@
+use crate::{FPError, Repository};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use sha1::{Digest, Sha1};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+use tracing::{debug, warn};
+
+pub const CACHED_REASON_PREFIX: &str = "Cached: ";
+
+#[derive(Debug, Clone)]
+pub struct Cache {
+    path: PathBuf,
+}
+
+impl Cache {
+    pub fn new(dir: &Path, sdk_key: &str, user_key: &str) -> Self {
+        let mut hasher = Sha1::new();
+        hasher.update(sdk_key.as_bytes());
+        hasher.update(b"\n");
+        hasher.update(user_key.as_bytes());
+        let name = format!("featureprobe_{:x}.json", hasher.finalize());
+        Self {
+            path: dir.join(name),
+        }
+    }
+
+    // overrides are shared by all users of a device
+    pub fn overrides(dir: &Path, sdk_key: &str) -> Self {
+        let mut hasher = Sha1::new();
+        hasher.update(sdk_key.as_bytes());
+        let name = format!("featureprobe_overrides_{:x}.json", hasher.finalize());
+        Self {
+            path: dir.join(name),
+        }
+    }
+
+    pub fn load(&self) -> Option<Repository> {
+        let mut repo: Repository = self.read_json()?;
+        for detail in repo.values_mut() {
+            detail.reason = format!("{}{}", CACHED_REASON_PREFIX, detail.reason);
+        }
+        Some(repo)
+    }
+
+    pub fn store(&self, repo: &Repository) -> Result<(), FPError> {
+        self.write_json(repo)
+    }
+
+    pub fn load_overrides(&self) -> Option<HashMap<String, Value>> {
+        self.read_json()
+    }
+
+    pub fn store_overrides(&self, overrides: &HashMap<String, Value>) -> Result<(), FPError> {
+        self.write_json(overrides)
+    }
+
+    fn read_json<T: DeserializeOwned>(&self) -> Option<T> {
+        let json_str = match fs::read_to_string(&self.path) {
+            Ok(s) => s,
+            Err(e) => {
+                debug!("load cache {:?} failed: {}", self.path, e);
+                return None;
+            }
+        };
+        match serde_json::from_str(&json_str) {
+            Ok(r) => Some(r),
+            Err(e) => {
+                warn!("invalid cache {:?}: {}", self.path, e);
+                None
+            }
+        }
+    }
+
+    fn write_json<T: Serialize>(&self, content: &T) -> Result<(), FPError> {
+        let json_str =
+            serde_json::to_string(content).map_err(|e| FPError::JsonError(e.to_string()))?;
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir).map_err(|e| FPError::CacheError(e.to_string()))?;
+        }
+        // write to a temporary file then rename, readers never see a partial file
+        let tmp_path = self.path.with_extension("json.tmp");
+        fs::write(&tmp_path, json_str).map_err(|e| FPError::CacheError(e.to_string()))?;
+        fs::rename(&tmp_path, &self.path).map_err(|e| FPError::CacheError(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FPDetail;
+    use serde_json::json;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let mut dir = std::env::temp_dir();
+        dir.push(format!(
+            "featureprobe_cache_test_{}_{}",
+            name,
+            std::process::id()
+        ));
+        dir
+    }
+
+    #[test]
+    fn test_store_and_load() {
+        let dir = temp_dir("store");
+        let cache = Cache::new(&dir, "sdk-key", "user-key");
+        assert!(cache.load().is_none());
+
+        let mut repo = Repository::default();
+        repo.insert(
+            "toggle".to_owned(),
+            FPDetail {
+                value: json!(true),
+                version: Some(3),
+                reason: "rule 0".to_owned(),
+                ..Default::default()
+            },
+        );
+        cache.store(&repo).unwrap();
+
+        let loaded = cache.load().unwrap();
+        let detail = loaded.get("toggle").unwrap();
+        assert_eq!(detail.value, json!(true));
+        assert_eq!(detail.version, Some(3));
+        assert_eq!(detail.reason, "Cached: rule 0");
+
+        let other_user = Cache::new(&dir, "sdk-key", "other-user-key");
+        assert!(other_user.load().is_none());
+
+        let overrides_cache = Cache::overrides(&dir, "sdk-key");
+        assert!(overrides_cache.load_overrides().is_none());
+        let mut overrides = HashMap::new();
+        overrides.insert("toggle".to_owned(), json!(false));
+        overrides_cache.store_overrides(&overrides).unwrap();
+        assert_eq!(overrides_cache.load_overrides(), Some(overrides));
+
+        let _ = fs::remove_dir_all(dir);
+    }
+}
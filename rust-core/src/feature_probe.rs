@@ -1,17 +1,40 @@
-use crate::sync::{SyncType, Synchronizer};
+use crate::cache::Cache;
+use crate::reason::{self, OVERRIDE_REASON, TYPE_MISMATCH_REASON};
+use crate::recorder::EventRecorder;
+use crate::retry::RetryPolicy;
+#[cfg(feature = "sse")]
+use crate::sse;
+use crate::subscriber::{SubscriberId, Subscribers, ToggleChange};
+use crate::sync::{FPState, SyncType, Synchronizer, UpdatePayload};
 use crate::user::FPUser;
-use crate::{FPDetail, Repository, SdkAuthorization};
+use crate::{FPDetail, FPError, Reason, Repository, SdkAuthorization};
+use dashmap::{mapref::entry::Entry, DashMap};
 use feature_probe_event::event::{AccessEvent, CustomEvent, DebugEvent, Event};
-use feature_probe_event::recorder::{unix_timestamp, EventRecorder};
+use feature_probe_event::recorder::unix_timestamp;
 use futures_util::FutureExt;
-use parking_lot::RwLock;
+use headers::HeaderValue;
+use parking_lot::{Mutex, RwLock};
+use serde::de::DeserializeOwned;
 use serde_json::Value;
 use socketio_rs::Client;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
+use tokio::{sync::mpsc, task::JoinHandle, time::Instant};
 use tracing::trace;
 use url::Url;
 
+// user key, toggle, variation index and version of a recorded access event
+type AccessKey = (String, String, Option<usize>, Option<u64>);
+
+// toggles and subscribers of one user, notified when overrides change
+type Observers = (Arc<RwLock<Repository>>, Subscribers);
+
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+const LIVENESS_INTERVAL: Duration = Duration::from_secs(30);
+const CATCH_UP_INTERVAL: Duration = Duration::from_secs(10);
+
 type SocketCallback = std::pin::Pin<Box<dyn futures_util::Future<Output = ()> + Send>>;
 
 #[derive(Clone)]
@@ -20,9 +43,83 @@ pub struct FeatureProbe {
     syncer: Option<Synchronizer>,
     event_recorder: Option<EventRecorder>,
     config: FPConfig,
-    user: FPUser,
+    user: Arc<RwLock<FPUser>>,
     should_stop: Arc<RwLock<bool>>,
-    socket: Option<Client>,
+    paused: Arc<RwLock<bool>>,
+    socket: Arc<Mutex<Option<Client>>>,
+    socket_task: Arc<Mutex<Option<JoinHandle<()>>>>,
+    connection_state: Arc<RwLock<ConnectionState>>,
+    subscribers: Subscribers,
+    client: reqwest::Client,
+    contexts: Arc<DashMap<String, UserContext>>,
+    context_key: Option<String>,
+    // the instance created by `new`, set in handles of `add_user`
+    primary: Option<Box<FeatureProbe>>,
+    overrides: Arc<RwLock<HashMap<String, Value>>>,
+    accessed: Arc<Mutex<HashSet<AccessKey>>>,
+}
+
+// state of an additional user, see `FeatureProbe::add_user`
+#[derive(Clone)]
+struct UserContext {
+    user: Arc<RwLock<FPUser>>,
+    repo: Arc<RwLock<Repository>>,
+    syncer: Synchronizer,
+    subscribers: Subscribers,
+    should_stop: Arc<RwLock<bool>>,
+}
+
+/// State of the realtime connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConnectionState {
+    /// Connecting or reconnecting after a failure
+    Connecting,
+    Connected,
+    /// Not connected, e.g. paused, closed or waiting to reconnect
+    #[default]
+    Disconnected,
+}
+
+/// How toggles are kept up to date after the first sync
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DataSourceMode {
+    /// Poll every `refresh_interval`, no realtime connection
+    Polling,
+    /// Sync on realtime updates and after reconnecting, no polling
+    Realtime,
+    /// Realtime updates and polling every `refresh_interval`, a long
+    /// interval makes polling a safety net for missed updates
+    #[default]
+    RealtimeWithPollingFallback,
+    /// Only the first sync, later syncs are up to the app,
+    /// e.g. by `identify_and_wait`
+    Manual,
+}
+
+impl DataSourceMode {
+    fn polling(self) -> bool {
+        matches!(
+            self,
+            DataSourceMode::Polling | DataSourceMode::RealtimeWithPollingFallback
+        )
+    }
+
+    fn realtime(self) -> bool {
+        matches!(
+            self,
+            DataSourceMode::Realtime | DataSourceMode::RealtimeWithPollingFallback
+        )
+    }
+}
+
+/// Transport of realtime updates from `FPConfig::realtime_url`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RealtimeTransport {
+    #[default]
+    SocketIo,
+    /// Plain http streaming, for networks that break websocket upgrades.
+    /// Requires the `sse` feature, socket.io is used without it.
+    Sse,
 }
 
 #[derive(Debug, Clone)]
@@ -31,25 +128,98 @@ pub struct FPConfig {
     pub events_url: Url,
     pub realtime_url: Url,
     pub client_sdk_key: String,
+    /// Interval of polling toggles, at least 1s
     pub refresh_interval: Duration,
+    /// Interval of posting access and custom events, at least 1s
+    pub events_flush_interval: Duration,
+    /// Max batches of events kept while the events endpoint is unreachable
+    pub events_capacity: usize,
+    /// Timeout of each toggles or events request
+    pub http_timeout: Duration,
     pub start_wait: Option<Duration>,
+    pub cache_dir: Option<PathBuf>,
+    pub retry_policy: RetryPolicy,
+    /// Record an access event only once per user, toggle, variation
+    /// and version during the lifetime of the instance.
+    pub dedup_access_events: bool,
+    pub data_source: DataSourceMode,
+    pub realtime_transport: RealtimeTransport,
+}
+
+// a local server and an empty sdk key, meant to be completed with struct
+// update syntax
+impl Default for FPConfig {
+    fn default() -> Self {
+        Self {
+            toggles_url: "http://127.0.0.1:4007/api/client-sdk/toggles"
+                .parse()
+                .unwrap(),
+            events_url: "http://127.0.0.1:4007/api/events".parse().unwrap(),
+            realtime_url: "http://127.0.0.1:4007/realtime".parse().unwrap(),
+            client_sdk_key: Default::default(),
+            refresh_interval: Duration::from_secs(60),
+            events_flush_interval: Duration::from_secs(5),
+            events_capacity: 100,
+            http_timeout: Duration::from_secs(3),
+            start_wait: None,
+            cache_dir: None,
+            retry_policy: Default::default(),
+            dedup_access_events: false,
+            data_source: Default::default(),
+            realtime_transport: Default::default(),
+        }
+    }
 }
 
 #[allow(dead_code)]
 impl FeatureProbe {
     pub fn new(config: FPConfig, user: FPUser) -> Self {
-        let mut slf = Self {
+        let mut slf = Self::init(config, user);
+        let _ = slf.start();
+        slf
+    }
+
+    /// Same as `new`, but returns the error if the first sync failed
+    /// within `config.start_wait`.
+    pub fn try_new(config: FPConfig, user: FPUser) -> Result<Self, FPError> {
+        SdkAuthorization(config.client_sdk_key.clone()).encode()?;
+        let mut slf = Self::init(config, user);
+        if let Err(e) = slf.start() {
+            slf.close();
+            return Err(e);
+        }
+        Ok(slf)
+    }
+
+    fn init(config: FPConfig, user: FPUser) -> Self {
+        let overrides = config
+            .cache_dir
+            .as_ref()
+            .and_then(|dir| Cache::overrides(dir, &config.client_sdk_key).load_overrides())
+            .unwrap_or_default();
+        let client = reqwest::Client::builder()
+            .timeout(config.http_timeout)
+            .build()
+            .unwrap_or_default();
+        Self {
             config,
-            user,
+            user: Arc::new(RwLock::new(user)),
             repo: Default::default(),
             syncer: Default::default(),
             event_recorder: Default::default(),
             should_stop: Arc::new(RwLock::new(false)),
-            socket: None,
-        };
-
-        slf.start();
-        slf
+            paused: Default::default(),
+            socket: Default::default(),
+            socket_task: Default::default(),
+            connection_state: Default::default(),
+            subscribers: Default::default(),
+            client,
+            contexts: Default::default(),
+            context_key: None,
+            primary: None,
+            overrides: Arc::new(RwLock::new(overrides)),
+            accessed: Default::default(),
+        }
     }
 
     // Just for test and bench
@@ -60,57 +230,304 @@ impl FeatureProbe {
             syncer: Default::default(),
             user: Default::default(),
             should_stop: Arc::new(RwLock::new(false)),
-            socket: None,
-            config: FPConfig {
-                toggles_url: "https://just_for_test.com".parse().unwrap(),
-                events_url: "https://just_for_test.com".parse().unwrap(),
-                realtime_url: "https://just_for_test.com".parse().unwrap(),
-                client_sdk_key: Default::default(),
-                refresh_interval: Default::default(),
-                start_wait: Default::default(),
-            },
+            paused: Default::default(),
+            socket: Default::default(),
+            socket_task: Default::default(),
+            connection_state: Default::default(),
+            subscribers: Default::default(),
+            client: Default::default(),
+            contexts: Default::default(),
+            context_key: None,
+            primary: None,
+            overrides: Default::default(),
+            accessed: Default::default(),
+            config: Default::default(),
+        }
+    }
+
+    pub fn state(&self) -> FPState {
+        match &self.syncer {
+            Some(syncer) => syncer.state(),
+            None => FPState::Ready,
         }
     }
 
+    /// Time of the last successful sync, None if toggles were never synced
+    pub fn last_synced_at(&self) -> Option<SystemTime> {
+        self.syncer.as_ref()?.last_synced_at()
+    }
+
+    /// Wait until toggles are synced, or loaded from cache and sync failed.
+    pub async fn wait_until_ready(&self, timeout: Duration) -> Result<(), FPError> {
+        match &self.syncer {
+            Some(syncer) => syncer.wait_until_ready(timeout).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Close the instance, a handle returned by `add_user` only removes its user.
+    /// Syncing stops at once, pending events are flushed in background,
+    /// use `shutdown` to wait for it.
     pub fn close(&self) {
-        // TODO: logging
+        if let Some(key) = &self.context_key {
+            self.remove_user(key);
+            return;
+        }
+        self.stop_syncing();
+        // stop reconnecting even without a runtime to drain on
+        let socket = self.take_socket();
+        match tokio::runtime::Handle::try_current() {
+            Ok(handle) => {
+                let slf = self.clone();
+                let timeout = self.config.http_timeout;
+                handle.spawn(async move { slf.drain(socket, timeout).await });
+            }
+            Err(_) => {
+                if let Some(recorder) = &self.event_recorder {
+                    if let Some(task) = recorder.stop_loop() {
+                        task.abort();
+                    }
+                }
+            }
+        }
+    }
+
+    /// Same as `close`, but wait until the realtime connection is closed and
+    /// pending events are flushed. Returns false if flushing failed or did not
+    /// finish within `timeout`.
+    pub async fn shutdown(&self, timeout: Duration) -> bool {
+        if let Some(key) = &self.context_key {
+            self.remove_user(key);
+            return true;
+        }
+        self.stop_syncing();
+        let socket = self.take_socket();
+        self.drain(socket, timeout).await
+    }
+
+    // disconnect and flush pending events after syncing stopped
+    async fn drain(&self, socket: Option<Client>, timeout: Duration) -> bool {
+        let drain = async {
+            disconnect_socket(socket).await;
+            match &self.event_recorder {
+                Some(recorder) => match recorder.stop().await {
+                    Ok(_) => true,
+                    Err(e) => {
+                        tracing::error!("flush events error: {}", e);
+                        false
+                    }
+                },
+                None => true,
+            }
+        };
+        tokio::time::timeout(timeout, drain).await.unwrap_or(false)
+    }
+
+    /// Suspend the instance, e.g. when the app enters background. Polling
+    /// stops and the realtime connection is closed at once, pending events
+    /// are flushed in background. Toggles can still be evaluated.
+    /// Only the instance returned by `new` can be paused, not a handle
+    /// returned by `add_user`.
+    pub fn pause(&self) {
+        if self.context_key.is_some() || *self.should_stop.read() {
+            return;
+        }
+        {
+            let mut paused = self.paused.write();
+            if *paused {
+                return;
+            }
+            *paused = true;
+        }
+        for syncer in self.syncers() {
+            syncer.pause();
+        }
+        let socket = self.take_socket();
+        let recorder = self.event_recorder.clone();
+        let flush_loop = recorder.as_ref().and_then(|r| r.stop_loop());
+        if let Ok(handle) = tokio::runtime::Handle::try_current() {
+            handle.spawn(async move {
+                disconnect_socket(socket).await;
+                if let Some(task) = flush_loop {
+                    let _ = task.await;
+                }
+                if let Some(recorder) = recorder {
+                    if let Err(e) = recorder.flush().await {
+                        tracing::error!("flush events error: {}", e);
+                    }
+                }
+            });
+        }
+    }
+
+    /// Resume a paused instance, e.g. when the app enters foreground.
+    /// Toggles are synced right away and realtime is reconnected, unless
+    /// `config.data_source` is `Manual`.
+    pub fn resume(&self) {
+        if self.context_key.is_some() || *self.should_stop.read() {
+            return;
+        }
+        {
+            let mut paused = self.paused.write();
+            if !*paused {
+                return;
+            }
+            *paused = false;
+        }
+        if self.syncer.is_none() {
+            return;
+        }
+        let mode = self.config.data_source;
+        if mode != DataSourceMode::Manual {
+            for syncer in self.syncers() {
+                let _ = syncer.start_sync(None, self.config.retry_policy.clone(), mode.polling());
+            }
+        }
+        if mode.realtime() {
+            self.connect_socket();
+        }
         if let Some(recorder) = &self.event_recorder {
-            recorder.flush();
+            recorder.start();
+        }
+    }
+
+    pub fn connection_state(&self) -> ConnectionState {
+        *self.connection_state.read()
+    }
+
+    pub fn is_paused(&self) -> bool {
+        *self.paused.read()
+    }
+
+    /// Evaluate toggles of another user in the same instance, e.g. on a shared
+    /// device. The returned handle has its own toggles and subscribers, but
+    /// shares http client, realtime connection and event recorder with `self`.
+    /// Adding a user key twice returns a handle to the same toggles, adding
+    /// the key of the primary user returns a handle to the primary toggles.
+    pub fn add_user(&self, user: FPUser) -> FeatureProbe {
+        let key = user.key.clone();
+        let primary = self.primary();
+        if primary.user.read().key == key {
+            return Self {
+                context_key: Some(key),
+                primary: Some(Box::new(primary.clone())),
+                ..primary.clone()
+            };
+        }
+        // the entry lock makes check and insert atomic, only one syncer is
+        // started for a key
+        let context = match self.contexts.entry(key.clone()) {
+            Entry::Occupied(e) => e.get().clone(),
+            Entry::Vacant(e) => e.insert(self.build_context(user)).clone(),
+        };
+        self.context_handle(key, context)
+    }
+
+    /// Stop syncing toggles of a user added by `add_user`.
+    pub fn remove_user(&self, key: &str) -> bool {
+        match self.contexts.remove(key) {
+            Some((_, context)) => {
+                context.syncer.stop();
+                true
+            }
+            None => false,
         }
-        let mut should_stop = self.should_stop.write();
-        *should_stop = true;
     }
 
     pub fn bool_value(&self, toggle: &str, default: bool) -> bool {
-        self.generic_value(toggle, default, |v| v.as_bool())
+        self.generic_value(toggle, default, true, |v| v.as_bool())
     }
 
     pub fn string_value(&self, toggle: &str, default: String) -> String {
-        self.generic_value(toggle, default, |v| v.as_str().map(|s| s.to_owned()))
+        self.generic_value(toggle, default, true, |v| v.as_str().map(|s| s.to_owned()))
     }
 
     pub fn number_value(&self, toggle: &str, default: f64) -> f64 {
-        self.generic_value(toggle, default, |v| v.as_f64())
+        self.generic_value(toggle, default, true, |v| v.as_f64())
+    }
+
+    /// Falls back to `default` if the value is not an integer or out of range.
+    pub fn int_value(&self, toggle: &str, default: i64) -> i64 {
+        self.generic_value(toggle, default, true, as_int)
     }
 
     pub fn json_value(&self, toggle: &str, default: Value) -> Value {
-        self.generic_value(toggle, default, |v| Some(v.to_owned()))
+        self.generic_value(toggle, default, true, |v| Some(v.to_owned()))
     }
 
     pub fn bool_detail(&self, toggle: &str, default: bool) -> FPDetail<bool> {
-        self.generic_detail(toggle, default, |v| v.as_bool())
+        self.generic_detail(toggle, default, true, |v| v.as_bool())
     }
 
     pub fn string_detail(&self, toggle: &str, default: String) -> FPDetail<String> {
-        self.generic_detail(toggle, default, |v| v.as_str().map(|x| x.to_owned()))
+        self.generic_detail(toggle, default, true, |v| v.as_str().map(|x| x.to_owned()))
     }
 
     pub fn number_detail(&self, toggle: &str, default: f64) -> FPDetail<f64> {
-        self.generic_detail(toggle, default, |v| v.as_f64())
+        self.generic_detail(toggle, default, true, |v| v.as_f64())
+    }
+
+    pub fn int_detail(&self, toggle: &str, default: i64) -> FPDetail<i64> {
+        self.generic_detail(toggle, default, true, as_int)
     }
 
     pub fn json_detail(&self, toggle: &str, default: Value) -> FPDetail<Value> {
-        self.generic_detail(toggle, default, |v| Some(v.to_owned()))
+        self.generic_detail(toggle, default, true, |v| Some(v.to_owned()))
+    }
+
+    /// Deserialize the toggle value into `T`, e.g. a remote config struct.
+    pub fn typed_value<T: DeserializeOwned>(&self, toggle: &str, default: T) -> T {
+        self.generic_value(toggle, default, true, |v| T::deserialize(v).ok())
+    }
+
+    /// Same as `typed_value`, a deserialization failure is reported as
+    /// `Reason::TypeMismatch`.
+    pub fn typed_detail<T: DeserializeOwned + Default>(
+        &self,
+        toggle: &str,
+        default: T,
+    ) -> FPDetail<T> {
+        self.generic_detail(toggle, default, true, |v| T::deserialize(v).ok())
+    }
+
+    /// Same as `bool_detail`, but never records access or debug events,
+    /// e.g. for logging or reading a toggle in a render loop.
+    pub fn peek_bool_detail(&self, toggle: &str, default: bool) -> FPDetail<bool> {
+        self.generic_detail(toggle, default, false, |v| v.as_bool())
+    }
+
+    pub fn peek_string_detail(&self, toggle: &str, default: String) -> FPDetail<String> {
+        self.generic_detail(toggle, default, false, |v| v.as_str().map(|x| x.to_owned()))
+    }
+
+    pub fn peek_number_detail(&self, toggle: &str, default: f64) -> FPDetail<f64> {
+        self.generic_detail(toggle, default, false, |v| v.as_f64())
+    }
+
+    pub fn peek_int_detail(&self, toggle: &str, default: i64) -> FPDetail<i64> {
+        self.generic_detail(toggle, default, false, as_int)
+    }
+
+    pub fn peek_json_detail(&self, toggle: &str, default: Value) -> FPDetail<Value> {
+        self.generic_detail(toggle, default, false, |v| Some(v.to_owned()))
+    }
+
+    /// Snapshot of all toggles, with overrides applied, taken under a single
+    /// read lock. Access events are recorded for every toggle if
+    /// `record_events` is true, pass false for debug screens or crash reports.
+    pub fn all_details(&self, record_events: bool) -> Repository {
+        let mut all = self.repo.read().clone();
+        for (toggle, value) in self.overrides.read().iter() {
+            all.insert(toggle.clone(), override_detail(value.clone()));
+        }
+        if record_events {
+            for (toggle, detail) in &all {
+                if detail.reason_kind() != Reason::Override {
+                    self.record_event(toggle, detail.clone());
+                }
+            }
+        }
+        all
     }
 
     pub fn track_event(&self, name: &str, value: Option<f64>) {
@@ -118,18 +535,257 @@ impl FeatureProbe {
             r.record_event(Event::CustomEvent(CustomEvent {
                 kind: "custom".to_string(),
                 time: unix_timestamp(),
-                user: self.user.key.clone(),
+                user: self.user.read().key.clone(),
                 name: name.to_string(),
                 value,
             }))
         }
     }
 
-    fn generic_value<T>(&self, toggle: &str, default: T, transform: fn(&Value) -> Option<T>) -> T {
+    /// Fetch toggles now, e.g. for pull to refresh, and return whether they
+    /// changed. Concurrent calls share one request.
+    pub async fn refresh(&self) -> Result<bool, FPError> {
+        match &self.syncer {
+            Some(syncer) => syncer.refresh().await,
+            None => Ok(false),
+        }
+    }
+
+    /// Switch the evaluated user, e.g. after login or logout. Toggles of the
+    /// new user are loaded from cache if any, and fetched in background.
+    /// A user added by `add_user` is removed, its toggles are taken over.
+    pub fn identify(&self, user: FPUser) {
+        if let Some(syncer) = self.switch_user(user) {
+            tokio::spawn(async move {
+                let _ = syncer.sync_now(SyncType::Polling).await;
+            });
+        }
+    }
+
+    /// Same as `identify`, but wait until toggles of the new user are fetched.
+    pub async fn identify_and_wait(&self, user: FPUser, timeout: Duration) -> Result<(), FPError> {
+        let syncer = match self.switch_user(user) {
+            Some(syncer) => syncer,
+            None => return Ok(()),
+        };
+        match tokio::time::timeout(timeout, syncer.sync_now(SyncType::Polling)).await {
+            Ok(r) => r,
+            Err(_) => Err(FPError::Timeout),
+        }
+    }
+
+    /// Subscribe changes of `toggle`, or of all toggles if `toggle` is None.
+    /// The callback is invoked after a polling or realtime sync changes
+    /// the value, variation or version of a toggle.
+    pub fn subscribe<F>(&self, toggle: Option<&str>, callback: F) -> SubscriberId
+    where
+        F: Fn(&ToggleChange) + Send + Sync + 'static,
+    {
+        self.subscribers
+            .subscribe(toggle.map(|t| t.to_owned()), Arc::new(callback))
+    }
+
+    pub fn unsubscribe(&self, id: SubscriberId) -> bool {
+        self.subscribers.unsubscribe(id)
+    }
+
+    /// Force the value of `toggle` locally, e.g. from a debug menu. Overrides
+    /// take precedence over synced toggles for all users of the instance,
+    /// are reported with reason `Override` and record no access events.
+    /// They are persisted in `cache_dir` if configured.
+    pub fn set_override(&self, toggle: &str, value: Value) {
+        let old = self.effective_details(toggle);
+        self.overrides.write().insert(toggle.to_owned(), value);
+        self.overrides_changed(toggle, old);
+    }
+
+    /// Remove the override of `toggle`, returns false if there was none.
+    pub fn clear_override(&self, toggle: &str) -> bool {
+        let old = self.effective_details(toggle);
+        if self.overrides.write().remove(toggle).is_none() {
+            return false;
+        }
+        self.overrides_changed(toggle, old);
+        true
+    }
+
+    pub fn clear_all_overrides(&self) {
+        let toggles: Vec<String> = self.overrides.read().keys().cloned().collect();
+        for toggle in toggles {
+            self.clear_override(&toggle);
+        }
+    }
+
+    // overrides apply to all users, subscribers of each are notified
+    fn overrides_changed(&self, toggle: &str, old: Vec<(Observers, Option<FPDetail<Value>>)>) {
+        self.store_overrides();
+        for ((repo, subscribers), old) in old {
+            let new = self.effective_detail(&repo, toggle);
+            if old != new {
+                subscribers.notify(&[ToggleChange {
+                    key: toggle.to_owned(),
+                    old,
+                    new,
+                }]);
+            }
+        }
+    }
+
+    fn store_overrides(&self) {
+        let dir = match &self.config.cache_dir {
+            Some(dir) => dir,
+            None => return,
+        };
+        let cache = Cache::overrides(dir, &self.config.client_sdk_key);
+        if let Err(e) = cache.store_overrides(&self.overrides.read()) {
+            tracing::warn!("store overrides failed: {:?}", e);
+        }
+    }
+
+    // detail seen by evaluation, without type conversion
+    fn effective_detail(&self, repo: &RwLock<Repository>, toggle: &str) -> Option<FPDetail<Value>> {
+        match self.overrides.read().get(toggle) {
+            Some(v) => Some(override_detail(v.clone())),
+            None => repo.read().get(toggle).cloned(),
+        }
+    }
+
+    // details of the primary user and all added users
+    fn effective_details(&self, toggle: &str) -> Vec<(Observers, Option<FPDetail<Value>>)> {
+        let primary = self.primary();
+        let mut observers = vec![(primary.repo.clone(), primary.subscribers.clone())];
+        observers.extend(
+            self.contexts
+                .iter()
+                .map(|c| (c.repo.clone(), c.subscribers.clone())),
+        );
+        observers
+            .into_iter()
+            .map(|(repo, subscribers)| {
+                let detail = self.effective_detail(&repo, toggle);
+                ((repo, subscribers), detail)
+            })
+            .collect()
+    }
+
+    // `self` unless this is a handle returned by `add_user`
+    fn primary(&self) -> &FeatureProbe {
+        self.primary.as_deref().unwrap_or(self)
+    }
+
+    fn switch_user(&self, user: FPUser) -> Option<Synchronizer> {
+        let syncer = self.syncer.clone()?;
+        let cache = self.cache_for(&user);
+        // one syncer per user key, an added user is not synced twice
+        let repo = match self.contexts.remove(&user.key) {
+            Some((_, context)) => {
+                context.syncer.stop();
+                let repo = context.repo.read().clone();
+                repo
+            }
+            None => cache.as_ref().and_then(|c| c.load()).unwrap_or_default(),
+        };
+        syncer.reset(self.toggles_url_for(&user), cache, repo);
+        *self.user.write() = user;
+        Some(syncer)
+    }
+
+    fn build_context(&self, user: FPUser) -> UserContext {
+        let cache = self.cache_for(&user);
+        let repo = cache.as_ref().and_then(|c| c.load()).unwrap_or_default();
+        let repo = Arc::new(RwLock::new(repo));
+        let subscribers = Subscribers::default();
+        let should_stop = Arc::new(RwLock::new(false));
+        let syncer = self.build_syncer(&user, repo.clone(), should_stop.clone(), &subscribers);
+        syncer.set_cache(cache);
+        if self.syncer.is_some() && !self.is_paused() {
+            let polling = self.config.data_source.polling();
+            let _ = syncer.start_sync(None, self.config.retry_policy.clone(), polling);
+        }
+
+        UserContext {
+            user: Arc::new(RwLock::new(user)),
+            repo,
+            syncer,
+            subscribers,
+            should_stop,
+        }
+    }
+
+    // an invalid sdk key is reported by `try_new`, requests fail without it
+    fn auth(&self) -> HeaderValue {
+        SdkAuthorization(self.config.client_sdk_key.clone())
+            .encode()
+            .unwrap_or_else(|e| {
+                tracing::error!("{}", e);
+                HeaderValue::from_static("")
+            })
+    }
+
+    fn context_handle(&self, key: String, context: UserContext) -> FeatureProbe {
+        Self {
+            repo: context.repo,
+            syncer: Some(context.syncer),
+            user: context.user,
+            should_stop: context.should_stop,
+            subscribers: context.subscribers,
+            context_key: Some(key),
+            primary: Some(Box::new(self.primary().clone())),
+            ..self.clone()
+        }
+    }
+
+    fn stop_syncing(&self) {
+        *self.should_stop.write() = true;
+        for syncer in self.syncers() {
+            syncer.stop();
+        }
+    }
+
+    // stop connecting, the connected socket is returned for disconnecting
+    fn take_socket(&self) -> Option<Client> {
+        if let Some(task) = self.socket_task.lock().take() {
+            task.abort();
+        }
+        self.set_connection_state(ConnectionState::Disconnected);
+        self.socket.lock().take()
+    }
+
+    // synchronizers of the primary user and all added users
+    fn syncers(&self) -> Vec<Synchronizer> {
+        self.user_syncers().into_iter().map(|(_, s)| s).collect()
+    }
+
+    fn user_syncers(&self) -> Vec<(FPUser, Synchronizer)> {
+        let mut syncers: Vec<(FPUser, Synchronizer)> = self
+            .syncer
+            .iter()
+            .map(|s| (self.user.read().clone(), s.clone()))
+            .collect();
+        syncers.extend(
+            self.contexts
+                .iter()
+                .map(|c| (c.user.read().clone(), c.syncer.clone())),
+        );
+        syncers
+    }
+
+    fn generic_value<T>(
+        &self,
+        toggle: &str,
+        default: T,
+        record: bool,
+        transform: fn(&Value) -> Option<T>,
+    ) -> T {
+        if let Some(v) = self.overrides.read().get(toggle) {
+            return transform(v).unwrap_or(default);
+        }
         let repo = self.repo.read();
         let detail = repo.get(toggle);
 
-        detail.map(|d| self.record_event(toggle, d.clone()));
+        if record {
+            detail.map(|d| self.record_event(toggle, d.clone()));
+        }
 
         match detail {
             None => default,
@@ -144,23 +800,39 @@ impl FeatureProbe {
         &self,
         toggle: &str,
         default: T,
+        record: bool,
         transform: fn(&Value) -> Option<T>,
     ) -> FPDetail<T> {
+        if let Some(v) = self.overrides.read().get(toggle) {
+            return match transform(v) {
+                None => FPDetail {
+                    value: default,
+                    reason: TYPE_MISMATCH_REASON.to_owned(),
+                    ..Default::default()
+                },
+                Some(v) => override_detail(v),
+            };
+        }
         let repo = self.repo.read();
         let detail = repo.get(toggle);
 
-        detail.map(|d| self.record_event(toggle, d.clone()));
+        if record {
+            detail.map(|d| self.record_event(toggle, d.clone()));
+        }
 
         match detail {
             None => FPDetail {
                 value: default,
-                reason: format!("Toggle {} not found", toggle),
+                reason: match self.state() {
+                    FPState::Initializing => reason::not_ready(toggle),
+                    _ => reason::not_found(toggle),
+                },
                 ..Default::default()
             },
             Some(d) => match transform(&d.value) {
                 None => FPDetail {
                     value: default,
-                    reason: "Value type mismatch".to_owned(),
+                    reason: TYPE_MISMATCH_REASON.to_owned(),
                     ..Default::default()
                 },
                 Some(v) => FPDetail {
@@ -179,79 +851,245 @@ impl FeatureProbe {
     fn record_event(&self, toggle: &str, detail: FPDetail<Value>) -> Option<()> {
         let recorder = self.event_recorder.clone()?;
         let toggle = toggle.to_owned();
-        let user = self.user.clone();
-        tokio::spawn(async move {
-            let ts = unix_timestamp();
+        let user = self.user.read().clone();
+        // recording is a mutex push, evaluation must not need a runtime
+        let ts = unix_timestamp();
+        if self.should_record_access(&user.key, &toggle, &detail) {
             record_access(&recorder, &user, toggle.clone(), &detail, ts);
-            record_debug(
-                &recorder,
-                &user,
-                toggle,
-                &detail,
-                detail.debug_until_time,
-                ts,
-            );
-        });
+        }
+        record_debug(
+            &recorder,
+            &user,
+            toggle,
+            &detail,
+            detail.debug_until_time,
+            ts,
+        );
         None
     }
 
-    fn start(&mut self) {
-        self.sync();
-        self.connect_socket();
+    fn should_record_access(&self, user: &str, toggle: &str, detail: &FPDetail<Value>) -> bool {
+        if !self.config.dedup_access_events {
+            return true;
+        }
+        let key = (
+            user.to_owned(),
+            toggle.to_owned(),
+            detail.variation_index,
+            detail.version,
+        );
+        self.accessed.lock().insert(key)
+    }
+
+    fn start(&mut self) -> Result<(), FPError> {
+        self.load_cache();
+        let result = self.sync();
+        if self.config.data_source.realtime() {
+            self.connect_socket();
+        }
         self.flush_events();
+        result
+    }
+
+    fn cache_for(&self, user: &FPUser) -> Option<Cache> {
+        let dir = self.config.cache_dir.as_ref()?;
+        Some(Cache::new(dir, &self.config.client_sdk_key, &user.key))
     }
 
-    fn sync(&mut self) {
+    fn load_cache(&mut self) {
+        let cache = self.cache_for(&self.user.read());
+        if let Some(repo) = cache.and_then(|c| c.load()) {
+            trace!("load {} toggles from cache", repo.len());
+            *self.repo.write() = repo;
+        }
+    }
+
+    fn toggles_url_for(&self, user: &FPUser) -> Url {
         let mut remote_url = self.config.toggles_url.clone();
-        remote_url.set_query(Some(&format!("user={}", self.user.as_base64())));
+        remote_url.set_query(Some(&format!("user={}", user.as_base64())));
+        remote_url
+    }
 
+    fn build_syncer(
+        &self,
+        user: &FPUser,
+        repo: Arc<RwLock<Repository>>,
+        should_stop: Arc<RwLock<bool>>,
+        subscribers: &Subscribers,
+    ) -> Synchronizer {
+        let remote_url = self.toggles_url_for(user);
         let refresh_interval = self.config.refresh_interval;
-        let auth = SdkAuthorization(self.config.client_sdk_key.clone()).encode();
-        let repo = self.repo.clone();
-        let should_stop = self.should_stop.clone();
-        let client = reqwest::Client::default();
-        let syncer = Synchronizer::new(
+        let auth = self.auth();
+        Synchronizer::new(
             remote_url,
             refresh_interval,
             auth,
             repo,
             should_stop,
-            client,
+            self.client.clone(),
+            subscribers.clone(),
+        )
+    }
+
+    fn sync(&mut self) -> Result<(), FPError> {
+        let user = self.user.read().clone();
+        let syncer = self.build_syncer(
+            &user,
+            self.repo.clone(),
+            self.should_stop.clone(),
+            &self.subscribers,
         );
+        syncer.set_cache(self.cache_for(&user));
 
-        syncer.start_sync(self.config.start_wait);
-        self.syncer = Some(syncer);
+        self.syncer = Some(syncer.clone());
+        syncer.start_sync(
+            self.config.start_wait,
+            self.config.retry_policy.clone(),
+            self.config.data_source.polling(),
+        )
     }
 
-    fn connect_socket(&mut self) {
-        let mut slf = self.clone();
-        let slf2 = self.clone();
-        tokio::spawn(async move {
-            let url = slf.config.realtime_url;
-            let nsp = url.path();
-            let server_sdk_key = slf.config.client_sdk_key.clone();
-            trace!("connect_socket {}", url);
-            let client = socketio_rs::ClientBuilder::new(url.clone())
-                .namespace(nsp)
-                .on(socketio_rs::Event::Connect, move |_, socket, _| {
-                    Self::socket_on_connect(socket, server_sdk_key.clone())
-                })
-                .on(
-                    "update",
-                    move |payload: Option<socketio_rs::Payload>, _, _| {
-                        Self::socket_on_update(slf2.clone(), payload)
-                    },
-                )
-                .on("error", |err, _, _| {
-                    async move { tracing::error!("socket on error: {:#?}", err) }.boxed()
-                })
-                .connect()
-                .await;
-            match client {
-                Err(e) => tracing::error!("connect_socket error: {:?}", e),
-                Ok(client) => slf.socket = Some(client),
-            };
+    // keep the realtime connection, reconnect with backoff until stopped,
+    // connections shorter than `LIVENESS_INTERVAL` count as failures
+    fn connect_socket(&self) {
+        let slf = self.clone();
+        let transport = self.config.realtime_transport;
+        if cfg!(not(feature = "sse")) && transport == RealtimeTransport::Sse {
+            tracing::warn!("sse feature is not enabled, fall back to socket.io");
+        }
+        let task = tokio::spawn(async move {
+            let mut failures = 0;
+            let mut catch_up = CatchUp::default();
+            while !*slf.should_stop.read() {
+                slf.set_connection_state(ConnectionState::Connecting);
+                let uptime = match transport {
+                    #[cfg(feature = "sse")]
+                    RealtimeTransport::Sse => slf.run_sse(&mut catch_up).await,
+                    _ => slf.run_socket(&mut catch_up).await,
+                };
+                if let Some(uptime) = uptime {
+                    // updates may be missed while disconnected
+                    catch_up.schedule();
+                    if uptime >= LIVENESS_INTERVAL {
+                        failures = 0;
+                    }
+                }
+                slf.set_connection_state(ConnectionState::Disconnected);
+                let delay = slf
+                    .config
+                    .retry_policy
+                    .delay(RECONNECT_INTERVAL, failures, None);
+                failures += 1;
+                trace!("reconnect socket after {:?}", delay);
+                tokio::time::sleep(delay).await;
+            }
         });
+        if let Some(previous) = self.socket_task.lock().replace(task) {
+            previous.abort();
+        }
+    }
+
+    // connect and wait until the connection is closed, returns how long it was
+    // up or `None` if connecting failed
+    async fn run_socket(&self, catch_up: &mut CatchUp) -> Option<Duration> {
+        let (closed_tx, mut closed_rx) = mpsc::unbounded_channel();
+        let client = match self.socket_builder(closed_tx).connect().await {
+            Ok(client) => client,
+            Err(e) => {
+                tracing::error!("connect_socket error: {:?}", e);
+                return None;
+            }
+        };
+        let connected_at = Instant::now();
+        *self.socket.lock() = Some(client);
+        // a broken transport is reported by the engine.io heartbeat
+        loop {
+            tokio::select! {
+                _ = closed_rx.recv() => break,
+                _ = catch_up.due() => self.catch_up(catch_up).await,
+            }
+        }
+        let socket = self.socket.lock().take();
+        disconnect_socket(socket).await;
+        Some(connected_at.elapsed())
+    }
+
+    // same as `run_socket`, updates are `update` events of the stream
+    #[cfg(feature = "sse")]
+    async fn run_sse(&self, catch_up: &mut CatchUp) -> Option<Duration> {
+        let url = self.config.realtime_url.clone();
+        let auth = self.auth();
+        trace!("connect sse {}", url);
+        let connected = match sse::client(self.config.http_timeout) {
+            Ok(client) => sse::connect(&client, url, auth, self.config.http_timeout).await,
+            Err(e) => Err(e),
+        };
+        let mut stream = match connected {
+            Ok(stream) => stream,
+            Err(e) => {
+                tracing::error!("connect sse error: {}", e);
+                return None;
+            }
+        };
+        let connected_at = Instant::now();
+        self.set_connection_state(ConnectionState::Connected);
+        loop {
+            tokio::select! {
+                // heartbeats are expected well within the liveness interval
+                event = stream.next(LIVENESS_INTERVAL * 2) => match event {
+                    Some(event) if event.event == "update" => {
+                        let payload = serde_json::from_str(&event.data).ok();
+                        self.apply_update(update_payload(payload)).await;
+                    }
+                    Some(_) => {}
+                    None => break,
+                },
+                _ = catch_up.due() => self.catch_up(catch_up).await,
+            }
+        }
+        Some(connected_at.elapsed())
+    }
+
+    async fn catch_up(&self, catch_up: &mut CatchUp) {
+        catch_up.done();
+        self.sync_all(SyncType::Realtime).await;
+    }
+
+    // reconnecting is done by `connect_socket`, `closed` is notified when the
+    // connection is closed or broken
+    fn socket_builder(&self, closed: mpsc::UnboundedSender<()>) -> socketio_rs::ClientBuilder {
+        let url = self.config.realtime_url.clone();
+        let nsp = url.path().to_owned();
+        let server_sdk_key = self.config.client_sdk_key.clone();
+        let slf = self.clone();
+        let slf2 = self.clone();
+        let closed2 = closed.clone();
+        trace!("connect_socket {}", url);
+        socketio_rs::ClientBuilder::new(url)
+            .namespace(nsp)
+            .reconnect(false)
+            .on(socketio_rs::Event::Connect, move |_, socket, _| {
+                slf.set_connection_state(ConnectionState::Connected);
+                Self::socket_on_connect(socket, server_sdk_key.clone())
+            })
+            .on(
+                "update",
+                move |payload: Option<socketio_rs::Payload>, _, _| {
+                    Self::socket_on_update(slf2.clone(), payload)
+                },
+            )
+            .on(socketio_rs::Event::Close, move |_, _, _| {
+                let _ = closed.send(());
+                async move { trace!("socket on close") }.boxed()
+            })
+            .on("error", move |err, _, _| {
+                let _ = closed2.send(());
+                async move { tracing::error!("socket on error: {:#?}", err) }.boxed()
+            })
+    }
+
+    fn set_connection_state(&self, state: ConnectionState) {
+        *self.connection_state.write() = state;
     }
 
     fn socket_on_connect(socket: socketio_rs::Socket, server_sdk_key: String) -> SocketCallback {
@@ -270,35 +1108,128 @@ impl FeatureProbe {
 
     fn socket_on_update(slf: Self, payload: Option<socketio_rs::Payload>) -> SocketCallback {
         trace!("socket_on_update: {:?}", payload);
+        let update = match payload {
+            Some(socketio_rs::Payload::Json(value)) => update_payload(Some(value)),
+            _ => Default::default(),
+        };
+        async move { slf.apply_update(update).await }.boxed()
+    }
 
-        async move {
-            if let Some(syncer) = &slf.syncer {
-                let _ = syncer.sync_now(SyncType::Realtime).await;
-            } else {
-                tracing::warn!("socket receive update event, but no synchronizer");
-            }
+    async fn apply_update(&self, update: UpdatePayload) {
+        let syncers = self.user_syncers();
+        if syncers.is_empty() {
+            tracing::warn!("socket receive update event, but no synchronizer");
+        }
+        for (user, syncer) in syncers {
+            // toggles in the payload are evaluated for one user only
+            let toggles_url =
+                (update.user.as_ref() == Some(&user.key)).then(|| self.toggles_url_for(&user));
+            let _ = syncer.apply_update(&update, toggles_url.as_ref()).await;
+        }
+    }
+
+    async fn sync_all(&self, t: SyncType) {
+        if *self.should_stop.read() {
+            return;
+        }
+        let syncers = self.syncers();
+        if syncers.is_empty() {
+            tracing::warn!("socket receive update event, but no synchronizer");
+        }
+        for syncer in syncers {
+            let _ = syncer.sync_now(t).await;
         }
-        .boxed()
     }
 
     fn flush_events(&mut self) {
         let events_url = self.config.events_url.clone();
-        let flush_interval = self.config.refresh_interval;
-        let auth = SdkAuthorization(self.config.client_sdk_key.clone()).encode();
-        let should_stop = self.should_stop.clone();
+        let flush_interval = self.config.events_flush_interval;
+        let auth = self.auth();
         let event_recorder = EventRecorder::new(
             events_url,
             auth,
-            (*crate::USER_AGENT).clone(),
+            self.client.clone(),
             flush_interval,
-            100,
-            should_stop,
+            self.config.events_capacity,
         );
+        event_recorder.start();
 
         self.event_recorder = Some(event_recorder);
     }
 }
 
+// integral floats are accepted as long as they are exact
+fn as_int(v: &Value) -> Option<i64> {
+    const MAX_EXACT: f64 = (1u64 << 53) as f64;
+    if let Some(i) = v.as_i64() {
+        return Some(i);
+    }
+    let f = v.as_f64()?;
+    if f.fract() == 0.0 && f.abs() <= MAX_EXACT {
+        return Some(f as i64);
+    }
+    None
+}
+
+// a missing or malformed payload is an empty update, toggles are fetched again
+fn update_payload(payload: Option<Value>) -> UpdatePayload {
+    let value = match payload {
+        Some(value) if value.is_object() => value,
+        _ => return Default::default(),
+    };
+    serde_json::from_value(value).unwrap_or_else(|e| {
+        tracing::warn!("invalid update payload: {}", e);
+        Default::default()
+    })
+}
+
+async fn disconnect_socket(socket: Option<Client>) {
+    if let Some(socket) = socket {
+        if let Err(e) = socket.disconnect().await {
+            tracing::error!("disconnect socket error: {:?}", e);
+        }
+    }
+}
+
+// catch-up sync after reconnecting, at most once per `CATCH_UP_INTERVAL` so a
+// flapping connection does not refetch toggles on every reconnect
+#[derive(Debug, Default)]
+struct CatchUp {
+    due: Option<Instant>,
+    last: Option<Instant>,
+}
+
+impl CatchUp {
+    fn schedule(&mut self) {
+        if self.due.is_none() {
+            let now = Instant::now();
+            let earliest = self.last.map(|last| last + CATCH_UP_INTERVAL);
+            self.due = Some(earliest.map_or(now, |earliest| earliest.max(now)));
+        }
+    }
+
+    // pending until the scheduled catch-up is due
+    async fn due(&self) {
+        match self.due {
+            Some(due) => tokio::time::sleep_until(due).await,
+            None => futures_util::future::pending().await,
+        }
+    }
+
+    fn done(&mut self) {
+        self.due = None;
+        self.last = Some(Instant::now());
+    }
+}
+
+fn override_detail<T: Default>(value: T) -> FPDetail<T> {
+    FPDetail {
+        value,
+        reason: OVERRIDE_REASON.to_owned(),
+        ..Default::default()
+    }
+}
+
 fn record_access(
     recorder: &EventRecorder,
     user: &FPUser,
@@ -366,9 +1297,12 @@ impl std::fmt::Debug for FeatureProbe {
 mod tests {
     use serde_json::json;
 
-    use super::FeatureProbe;
-    use crate::Repository;
-    use std::{collections::HashMap, fs, path::PathBuf};
+    use super::{update_payload, CatchUp, FeatureProbe, CATCH_UP_INTERVAL};
+    use crate::{FPUser, Reason, Repository};
+    use parking_lot::Mutex;
+    use serde::Deserialize;
+    use std::{collections::HashMap, fs, path::PathBuf, sync::Arc};
+    use tokio::time::Instant;
 
     #[test]
     fn test_bool() {
@@ -380,6 +1314,47 @@ mod tests {
         assert!(detail.value);
         assert_eq!(detail.version, Some(1));
         assert_eq!(detail.rule_index, Some(0));
+        assert_eq!(detail.reason_kind(), Reason::RuleMatch { index: 0 });
+
+        let detail = fp.bool_detail("missing_toggle", false);
+        assert_eq!(detail.reason_kind(), Reason::NotFound);
+        let detail = fp.bool_detail("string_toggle", false);
+        assert_eq!(detail.reason_kind(), Reason::TypeMismatch);
+    }
+
+    #[test]
+    fn test_update_payload() {
+        let update = update_payload(Some(json!({
+            "version": 3,
+            "user": "some-user-key",
+            "toggles": {
+                "bool_toggle": { "value": true, "reason": "rule 0", "trackAccessEvents": false }
+            }
+        })));
+        assert_eq!(update.version, Some(3));
+        assert_eq!(update.user.as_deref(), Some("some-user-key"));
+        assert_eq!(update.toggles.unwrap()["bool_toggle"].value, json!(true));
+
+        // what servers send today, and malformed payloads
+        for payload in [json!(""), json!({ "version": "x" })] {
+            let update = update_payload(Some(payload));
+            assert!(update.version.is_none() && update.toggles.is_none());
+        }
+        assert!(update_payload(None).toggles.is_none());
+    }
+
+    #[test]
+    fn test_catch_up() {
+        let mut catch_up = CatchUp::default();
+        assert!(catch_up.due.is_none());
+        catch_up.schedule();
+        assert!(catch_up.due.unwrap() <= Instant::now());
+
+        // reconnecting again soon delays the next catch-up
+        catch_up.done();
+        catch_up.schedule();
+        let last = catch_up.last.unwrap();
+        assert_eq!(catch_up.due, Some(last + CATCH_UP_INTERVAL));
     }
 
     #[test]
@@ -394,6 +1369,28 @@ mod tests {
         assert_eq!(detail.rule_index, Some(0));
     }
 
+    #[test]
+    fn test_int() {
+        let repo = load_json();
+        let fp = FeatureProbe::new_with(repo);
+        assert_eq!(fp.int_value("number_toggle", 0), 1);
+
+        let detail = fp.int_detail("number_toggle", 0);
+        assert_eq!(detail.value, 1);
+        assert_eq!(detail.rule_index, Some(0));
+
+        fp.set_override("float", json!(1.5));
+        fp.set_override("whole_float", json!(2.0));
+        fp.set_override("too_large", json!(u64::MAX));
+        assert_eq!(fp.int_value("float", 7), 7);
+        assert_eq!(fp.int_value("whole_float", 7), 2);
+        let detail = fp.int_detail("too_large", 7);
+        assert_eq!(detail.value, 7);
+        assert_eq!(detail.reason_kind(), Reason::TypeMismatch);
+        fp.set_override("large", json!(i64::MAX));
+        assert_eq!(fp.int_value("large", 7), i64::MAX);
+    }
+
     #[test]
     fn test_string() {
         let repo = load_json();
@@ -428,6 +1425,136 @@ mod tests {
         assert_eq!(detail.rule_index, Some(0));
     }
 
+    #[test]
+    fn test_override() {
+        let repo = load_json();
+        let fp = FeatureProbe::new_with(repo);
+        let changes = Arc::new(Mutex::new(vec![]));
+        let changes_clone = changes.clone();
+        fp.subscribe(Some("bool_toggle"), move |c| {
+            changes_clone.lock().push(c.clone());
+        });
+
+        fp.set_override("bool_toggle", json!(false));
+        assert!(!fp.bool_value("bool_toggle", true));
+        let detail = fp.bool_detail("bool_toggle", true);
+        assert!(!detail.value);
+        assert_eq!(detail.reason, "Override");
+        assert_eq!(detail.reason_kind(), Reason::Override);
+        assert_eq!(detail.rule_index, None);
+        assert_eq!(
+            fp.string_detail("bool_toggle", "x".to_owned()).reason,
+            "Value type mismatch"
+        );
+
+        fp.set_override("new_toggle", json!("v"));
+        assert_eq!(fp.string_value("new_toggle", "x".to_owned()), "v");
+
+        assert!(fp.clear_override("bool_toggle"));
+        assert!(!fp.clear_override("bool_toggle"));
+        assert!(fp.bool_value("bool_toggle", false));
+
+        fp.clear_all_overrides();
+        assert_eq!(fp.string_value("new_toggle", "x".to_owned()), "x");
+
+        let changes = changes.lock();
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[0].old.as_ref().unwrap().value, json!(true));
+        assert_eq!(changes[0].new.as_ref().unwrap().value, json!(false));
+        assert_eq!(changes[1].new.as_ref().unwrap().value, json!(true));
+    }
+
+    #[test]
+    fn test_added_users() {
+        let repo = load_json();
+        let fp = FeatureProbe::new_with(repo);
+        let other = fp.add_user(FPUser::new("other-user-key"));
+        let changes = Arc::new(Mutex::new(vec![]));
+        for handle in [&fp, &other] {
+            let changes = changes.clone();
+            handle.subscribe(Some("bool_toggle"), move |c| {
+                changes.lock().push(c.clone());
+            });
+        }
+
+        // overrides apply to all users
+        other.set_override("bool_toggle", json!(false));
+        assert!(!fp.bool_value("bool_toggle", true));
+        assert_eq!(changes.lock().len(), 2);
+
+        // the key of the primary user returns the primary toggles
+        let primary = fp.add_user(fp.user.read().clone());
+        assert_eq!(primary.number_value("number_toggle", 0.0), 1.0);
+        assert_eq!(other.number_value("number_toggle", 0.0), 0.0);
+        primary.clear_override("bool_toggle");
+        assert_eq!(changes.lock().len(), 4);
+        primary.close();
+        assert!(fp.remove_user("other-user-key"));
+        assert!(fp.bool_value("bool_toggle", false));
+    }
+
+    #[test]
+    fn test_all_details() {
+        let repo = load_json();
+        let len = repo.len();
+        let fp = FeatureProbe::new_with(repo);
+        fp.set_override("bool_toggle", json!(false));
+        fp.set_override("new_toggle", json!(1));
+
+        let all = fp.all_details(false);
+        assert_eq!(all.len(), len + 1);
+        assert_eq!(all["bool_toggle"].value, json!(false));
+        assert_eq!(all["bool_toggle"].reason, "Override");
+        assert_eq!(all["number_toggle"].value, json!(1));
+        assert_eq!(all["number_toggle"].rule_index, Some(0));
+    }
+
+    #[test]
+    fn test_dedup_access_events() {
+        let repo = load_json();
+        let mut fp = FeatureProbe::new_with(repo);
+        let detail = fp.json_detail("bool_toggle", json!(false));
+        assert!(fp.should_record_access("user", "bool_toggle", &detail));
+        assert!(fp.should_record_access("user", "bool_toggle", &detail));
+
+        fp.config.dedup_access_events = true;
+        assert!(fp.should_record_access("user", "bool_toggle", &detail));
+        assert!(!fp.should_record_access("user", "bool_toggle", &detail));
+        assert!(fp.should_record_access("other", "bool_toggle", &detail));
+        let mut updated = detail.clone();
+        updated.version = Some(2);
+        assert!(fp.should_record_access("user", "bool_toggle", &updated));
+
+        let peek = fp.peek_bool_detail("bool_toggle", false);
+        assert!(peek.value);
+        assert_eq!(peek.rule_index, Some(0));
+    }
+
+    #[test]
+    fn test_typed() {
+        #[derive(Debug, Default, PartialEq, Deserialize)]
+        struct Config {
+            v: String,
+            variation_0: String,
+        }
+
+        let repo = load_json();
+        let fp = FeatureProbe::new_with(repo);
+        let config: Config = fp.typed_value("json_toggle", Default::default());
+        assert_eq!(config.v, "v1");
+        assert_eq!(config.variation_0, "c2");
+
+        let detail = fp.typed_detail("json_toggle", Config::default());
+        assert_eq!(detail.value.v, "v1");
+        assert_eq!(detail.reason_kind(), Reason::RuleMatch { index: 0 });
+
+        let detail = fp.typed_detail("string_toggle", Config::default());
+        assert_eq!(detail.value, Config::default());
+        assert_eq!(detail.reason_kind(), Reason::TypeMismatch);
+
+        assert_eq!(fp.typed_value("number_toggle", 0u32), 1);
+    }
+
     fn load_json() -> Repository {
         let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
         path.push("resources/fixtures/toggles.json");
@@ -1,9 +1,22 @@
+mod cache;
 mod feature_probe;
+mod reason;
+mod recorder;
+mod retry;
+#[cfg(feature = "sse")]
+mod sse;
+mod subscriber;
 mod sync;
 mod user;
 
+pub use crate::reason::Reason;
+pub use crate::retry::RetryPolicy;
+pub use crate::subscriber::{SubscriberId, ToggleChange};
+pub use crate::sync::FPState;
 pub use crate::user::FPUser;
-pub use feature_probe::{FPConfig, FeatureProbe};
+pub use feature_probe::{
+    ConnectionState, DataSourceMode, FPConfig, FeatureProbe, RealtimeTransport,
+};
 use lazy_static::lazy_static;
 pub use url::Url;
 
@@ -11,7 +24,7 @@ use headers::{Error, Header, HeaderName, HeaderValue};
 use http::header::AUTHORIZATION;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::{collections::HashMap, env};
+use std::{collections::HashMap, env, time::Duration};
 use thiserror::Error;
 
 include!(concat!(env!("OUT_DIR"), "/target_os.rs"));
@@ -36,8 +49,15 @@ pub struct FPDetail<T: Default> {
     pub debug_until_time: Option<u128>,
 }
 
+impl<T: Default> FPDetail<T> {
+    /// Structured `reason`, the raw text is kept for display.
+    pub fn reason_kind(&self) -> Reason {
+        Reason::parse(&self.reason)
+    }
+}
+
 #[non_exhaustive]
-#[derive(Debug, Error)]
+#[derive(Debug, Clone, Error)]
 pub enum FPError {
     #[error("invalid json: {0}")]
     JsonError(String),
@@ -45,14 +65,43 @@ pub enum FPError {
     HttpError(String),
     #[error("invalid url: {0}")]
     UrlError(String),
+    #[error("cache error: {0}")]
+    CacheError(String),
+    #[error("unauthorized: status code {status}")]
+    Unauthorized { status: u16 },
+    #[error("request timeout")]
+    Timeout,
+    #[error("client error: status code {status}")]
+    ClientError { status: u16 },
+    #[error("server error: status code {status}")]
+    ServerError {
+        status: u16,
+        retry_after: Option<Duration>,
+    },
+    #[error("rate limited, retry after {retry_after:?}")]
+    RateLimited { retry_after: Option<Duration> },
+    #[error("invalid payload: {0}")]
+    InvalidPayload(String),
+}
+
+impl FPError {
+    /// Delay requested by server with Retry-After header
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            FPError::RateLimited { retry_after } => *retry_after,
+            FPError::ServerError { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
 pub struct SdkAuthorization(pub String);
 
 impl SdkAuthorization {
-    pub fn encode(&self) -> HeaderValue {
-        HeaderValue::from_str(&self.0).expect("valid header value")
+    pub fn encode(&self) -> Result<HeaderValue, FPError> {
+        HeaderValue::from_str(&self.0)
+            .map_err(|e| FPError::HttpError(format!("invalid sdk key: {}", e)))
     }
 }
 
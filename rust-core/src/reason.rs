@@ -0,0 +1,80 @@
+use crate::cache::CACHED_REASON_PREFIX;
+
+pub const OVERRIDE_REASON: &str = "Override";
+pub const TYPE_MISMATCH_REASON: &str = "Value type mismatch";
+
+const NOT_READY_SUFFIX: &str = " not found, sdk not ready";
+
+/// Structured form of `FPDetail::reason`, see `FPDetail::reason_kind`.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reason {
+    /// Matched the rule at `index` of the toggle
+    RuleMatch { index: usize },
+    /// No rule matched, the default serve was used
+    Default,
+    /// The toggle is disabled on the server
+    Disabled,
+    /// The toggle does not exist
+    NotFound,
+    /// The toggle value can not be converted to the requested type
+    TypeMismatch,
+    /// The value was set by `FeatureProbe::set_override`
+    Override,
+    /// Loaded from cache, not synced yet
+    Cached,
+    /// The toggle was not found before the first sync
+    NotReady,
+    /// Any other text, e.g. an evaluation error from the server
+    Unknown,
+}
+
+impl Reason {
+    pub fn parse(reason: &str) -> Self {
+        if reason.starts_with(CACHED_REASON_PREFIX) {
+            return Reason::Cached;
+        }
+        if let Some(index) = reason.strip_prefix("rule ") {
+            return match index.trim().parse() {
+                Ok(index) => Reason::RuleMatch { index },
+                Err(_) => Reason::Unknown,
+            };
+        }
+        match reason {
+            OVERRIDE_REASON => Reason::Override,
+            TYPE_MISMATCH_REASON => Reason::TypeMismatch,
+            r if r.starts_with("default") => Reason::Default,
+            r if r.starts_with("disabled") => Reason::Disabled,
+            r if r.starts_with("Toggle ") && r.ends_with(NOT_READY_SUFFIX) => Reason::NotReady,
+            r if r.starts_with("Toggle ") && r.ends_with(" not found") => Reason::NotFound,
+            _ => Reason::Unknown,
+        }
+    }
+}
+
+pub(crate) fn not_found(toggle: &str) -> String {
+    format!("Toggle {} not found", toggle)
+}
+
+pub(crate) fn not_ready(toggle: &str) -> String {
+    format!("Toggle {}{}", toggle, NOT_READY_SUFFIX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Reason::parse("rule 2"), Reason::RuleMatch { index: 2 });
+        assert_eq!(Reason::parse("rule x"), Reason::Unknown);
+        assert_eq!(Reason::parse("default."), Reason::Default);
+        assert_eq!(Reason::parse("disabled"), Reason::Disabled);
+        assert_eq!(Reason::parse("Cached: rule 0"), Reason::Cached);
+        assert_eq!(Reason::parse("Override"), Reason::Override);
+        assert_eq!(Reason::parse("Value type mismatch"), Reason::TypeMismatch);
+        assert_eq!(Reason::parse(&not_found("a")), Reason::NotFound);
+        assert_eq!(Reason::parse(&not_ready("a")), Reason::NotReady);
+        assert_eq!(Reason::parse("PrerequisitesError"), Reason::Unknown);
+    }
+}
@@ -0,0 +1,408 @@
+use crate::sync::{http_error, status_error};
+use crate::FPError;
+use feature_probe_event::event::{Access, CountValue, Event, PackedData, ToggleCounter, Variation};
+use headers::HeaderValue;
+use parking_lot::Mutex;
+use reqwest::{
+    header::{AUTHORIZATION, CONTENT_ENCODING, CONTENT_TYPE, USER_AGENT},
+    Client, Method,
+};
+#[cfg(feature = "compression")]
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{sync::oneshot, task::JoinHandle};
+use tracing::{debug, error};
+use url::Url;
+
+const MIN_FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+// Unlike `feature_probe_event::recorder::EventRecorder`, flush can be awaited
+// and the flush loop can be stopped without losing the last batch.
+#[derive(Debug, Clone)]
+pub struct EventRecorder {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    events_url: Url,
+    auth: HeaderValue,
+    client: Client,
+    flush_interval: Duration,
+    // max packed batches kept while the events endpoint is unreachable
+    capacity: usize,
+    incoming_events: Mutex<Vec<Event>>,
+    packed_data: Mutex<VecDeque<PackedData>>,
+    task: Mutex<Option<FlushTask>>,
+    // set once the events endpoint rejected a gzip body
+    #[cfg(feature = "compression")]
+    plain_body: AtomicBool,
+}
+
+// flush loop and the sender to stop it
+type FlushTask = (JoinHandle<()>, oneshot::Sender<()>);
+
+impl EventRecorder {
+    pub fn new(
+        events_url: Url,
+        auth: HeaderValue,
+        client: Client,
+        flush_interval: Duration,
+        capacity: usize,
+    ) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                events_url,
+                auth,
+                client,
+                // `tokio::time::interval` panics on a zero period
+                flush_interval: flush_interval.max(MIN_FLUSH_INTERVAL),
+                capacity,
+                incoming_events: Default::default(),
+                packed_data: Default::default(),
+                task: Default::default(),
+                #[cfg(feature = "compression")]
+                plain_body: Default::default(),
+            }),
+        }
+    }
+
+    /// Flush events every `flush_interval` until `stop`.
+    pub fn start(&self) {
+        let inner = self.inner.clone();
+        let (stop_tx, mut stop_rx) = oneshot::channel();
+        let task = tokio::spawn(async move {
+            let mut interval = tokio::time::interval(inner.flush_interval);
+            interval.tick().await;
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {
+                        if let Err(e) = inner.flush().await {
+                            error!("flush events error: {}", e);
+                        }
+                    }
+                    _ = &mut stop_rx => break,
+                }
+            }
+        });
+        if let Some((previous, _)) = self.inner.task.lock().replace((task, stop_tx)) {
+            previous.abort();
+        }
+    }
+
+    /// Stop the flush loop, a flush in progress is finished first.
+    /// Pending events are flushed once more.
+    pub async fn stop(&self) -> Result<(), FPError> {
+        if let Some(task) = self.stop_loop() {
+            let _ = task.await;
+        }
+        self.flush().await
+    }
+
+    /// Signal the flush loop to stop, `start` may be called again right away.
+    /// The returned task finishes after a flush in progress.
+    pub fn stop_loop(&self) -> Option<JoinHandle<()>> {
+        let (task, stop_tx) = self.inner.task.lock().take()?;
+        let _ = stop_tx.send(());
+        Some(task)
+    }
+
+    pub async fn flush(&self) -> Result<(), FPError> {
+        self.inner.flush().await
+    }
+
+    pub fn record_event(&self, event: Event) {
+        self.inner.incoming_events.lock().push(event);
+    }
+}
+
+impl Inner {
+    async fn flush(&self) -> Result<(), FPError> {
+        let packed_data = {
+            let events = std::mem::take(&mut *self.incoming_events.lock());
+            let mut packed_data = self.packed_data.lock();
+            if !events.is_empty() {
+                if packed_data.len() >= self.capacity {
+                    let _ = packed_data.pop_front();
+                }
+                packed_data.push_back(build_packed_data(events));
+            }
+            std::mem::take(&mut *packed_data)
+        };
+        if packed_data.is_empty() {
+            return Ok(());
+        }
+
+        let result = self.post(&packed_data).await;
+        if result.is_err() {
+            // put back before batches packed in the meantime
+            let mut guard = self.packed_data.lock();
+            let newer = std::mem::replace(&mut *guard, packed_data);
+            guard.extend(newer);
+            while guard.len() > self.capacity {
+                let _ = guard.pop_front();
+            }
+        }
+        result
+    }
+
+    async fn post(&self, packed_data: &VecDeque<PackedData>) -> Result<(), FPError> {
+        let body =
+            serde_json::to_vec(packed_data).map_err(|e| FPError::JsonError(e.to_string()))?;
+        #[cfg(feature = "compression")]
+        if !self.plain_body.load(Ordering::Relaxed) {
+            match self.send(gzip(&body)?, Some("gzip")).await {
+                // 400 from servers that parse the body as json regardless
+                Err(FPError::ClientError { status: 400 | 415 }) => {
+                    debug!("gzip body rejected, post events without compression");
+                    self.plain_body.store(true, Ordering::Relaxed);
+                }
+                result => return result,
+            }
+        }
+        self.send(body, None).await
+    }
+
+    async fn send(&self, body: Vec<u8>, encoding: Option<&'static str>) -> Result<(), FPError> {
+        let mut request = self
+            .client
+            .request(Method::POST, self.events_url.clone())
+            .header(AUTHORIZATION, self.auth.clone())
+            .header(USER_AGENT, &*crate::USER_AGENT)
+            .header(CONTENT_TYPE, "application/json");
+        if let Some(encoding) = encoding {
+            request = request.header(CONTENT_ENCODING, encoding);
+        }
+        let request = request.body(body);
+
+        debug!("flush req: {:?}", request);
+        let resp = request.send().await.map_err(http_error)?;
+        debug!("flush resp: {:?}", resp);
+        let status = resp.status();
+        if !status.is_success() {
+            return Err(status_error(status, resp.headers()));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "compression")]
+fn gzip(body: &[u8]) -> Result<Vec<u8>, FPError> {
+    use flate2::{write::GzEncoder, Compression};
+    use std::io::Write;
+
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder
+        .write_all(body)
+        .and_then(|_| encoder.finish())
+        .map_err(|e| FPError::HttpError(format!("gzip events error: {}", e)))
+}
+
+fn build_packed_data(events: Vec<Event>) -> PackedData {
+    let access = build_access(&events);
+    let events = events
+        .into_iter()
+        .filter(|e| match e {
+            Event::AccessEvent(access_event) => access_event.track_access_events,
+            _ => true,
+        })
+        .collect();
+    PackedData { events, access }
+}
+
+fn build_access(events: &[Event]) -> Access {
+    let mut start_time = u128::MAX;
+    let mut end_time = 0;
+    let mut counters: HashMap<Variation, CountValue> = HashMap::new();
+
+    for e in events {
+        if let Event::AccessEvent(access_event) = e {
+            start_time = start_time.min(access_event.time);
+            end_time = end_time.max(access_event.time);
+            let variation = Variation {
+                key: access_event.key.clone(),
+                version: access_event.version,
+                index: access_event.variation_index,
+            };
+            let count_value = counters.entry(variation).or_insert(CountValue {
+                count: 0,
+                value: access_event.value.clone(),
+            });
+            count_value.count += 1;
+        }
+    }
+
+    let mut access = Access {
+        start_time,
+        end_time,
+        counters: Default::default(),
+    };
+    for (k, v) in counters {
+        let counter = ToggleCounter {
+            index: k.index,
+            version: k.version,
+            value: v.value,
+            count: v.count,
+        };
+        access.counters.entry(k.key).or_default().push(counter);
+    }
+    access
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SdkAuthorization;
+    use axum::{
+        body::Bytes,
+        extract::Extension,
+        http::{HeaderMap, StatusCode},
+        routing::post,
+        Router,
+    };
+    use feature_probe_event::event::{AccessEvent, CustomEvent};
+    use flate2::read::GzDecoder;
+    use serde_json::{json, Value};
+    use std::{io::Read, net::SocketAddr};
+
+    #[tokio::test]
+    async fn test_stop_flushes_events() {
+        let port = 19023;
+        let received = setup_events_api(port, true).await;
+        let recorder = build_recorder(port, Duration::from_secs(60));
+        recorder.start();
+
+        recorder.record_event(access_event("toggle", 1));
+        recorder.record_event(access_event("toggle", 1));
+        recorder.record_event(Event::CustomEvent(CustomEvent {
+            kind: "custom".to_owned(),
+            time: 1,
+            user: "user".to_owned(),
+            name: "purchase".to_owned(),
+            value: None,
+        }));
+        assert!(recorder.stop().await.is_ok());
+
+        let received = received.lock();
+        assert_eq!(received.len(), 1);
+        let (gzip, body) = &received[0];
+        assert_eq!(*gzip, cfg!(feature = "compression"));
+        let packed = &body[0];
+        assert_eq!(packed["access"]["counters"]["toggle"][0]["count"], json!(2));
+        assert_eq!(packed["events"].as_array().unwrap().len(), 1);
+    }
+
+    #[cfg(feature = "compression")]
+    #[tokio::test]
+    async fn test_gzip_rejected() {
+        let port = 19029;
+        let received = setup_events_api(port, false).await;
+        let recorder = build_recorder(port, Duration::from_secs(60));
+
+        recorder.record_event(access_event("toggle", 1));
+        assert!(recorder.flush().await.is_ok());
+        assert!(recorder.inner.plain_body.load(Ordering::Relaxed));
+        recorder.record_event(access_event("toggle", 1));
+        assert!(recorder.flush().await.is_ok());
+
+        let received = received.lock();
+        assert_eq!(received.len(), 2);
+        assert!(received.iter().all(|(gzip, _)| !gzip));
+        assert_eq!(
+            received[0].1[0]["access"]["counters"]["toggle"][0]["count"],
+            json!(1)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_flush_failed_keeps_events() {
+        // nothing listens on this port
+        let recorder = build_recorder(19024, Duration::from_secs(1));
+        recorder.record_event(access_event("toggle", 1));
+        assert!(recorder.flush().await.is_err());
+        assert!(recorder.flush().await.is_err());
+        assert_eq!(recorder.inner.packed_data.lock().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_zero_flush_interval() {
+        let recorder = build_recorder(19024, Duration::ZERO);
+        recorder.start();
+        let task = recorder.stop_loop().unwrap();
+        assert!(task.await.is_ok());
+    }
+
+    fn build_recorder(port: u16, flush_interval: Duration) -> EventRecorder {
+        let events_url = format!("http://127.0.0.1:{}/api/events", port);
+        EventRecorder::new(
+            events_url.parse().unwrap(),
+            SdkAuthorization("client-sdk-key".to_owned())
+                .encode()
+                .unwrap(),
+            Client::new(),
+            flush_interval,
+            10,
+        )
+    }
+
+    fn access_event(key: &str, variation_index: usize) -> Event {
+        Event::AccessEvent(AccessEvent {
+            kind: "access".to_owned(),
+            time: 1,
+            key: key.to_owned(),
+            user: "user".to_owned(),
+            value: json!(true),
+            variation_index,
+            version: Some(1),
+            rule_index: None,
+            track_access_events: false,
+        })
+    }
+
+    // received bodies and whether they were gzip encoded
+    type Received = Arc<Mutex<Vec<(bool, Value)>>>;
+
+    // a gzip body is rejected unless `accept_gzip`
+    async fn setup_events_api(port: u16, accept_gzip: bool) -> Received {
+        let received: Received = Default::default();
+        let app = Router::new()
+            .route(
+                "/api/events",
+                post(
+                    move |headers: HeaderMap,
+                          body: Bytes,
+                          Extension(received): Extension<Received>| async move {
+                        let gzip = headers
+                            .get(CONTENT_ENCODING)
+                            .is_some_and(|encoding| encoding == "gzip");
+                        if gzip && !accept_gzip {
+                            return StatusCode::UNSUPPORTED_MEDIA_TYPE;
+                        }
+                        let mut json = vec![];
+                        if gzip {
+                            GzDecoder::new(&body[..]).read_to_end(&mut json).unwrap();
+                        } else {
+                            json = body.to_vec();
+                        }
+                        received
+                            .lock()
+                            .push((gzip, serde_json::from_slice(&json).unwrap()));
+                        StatusCode::OK
+                    },
+                ),
+            )
+            .layer(Extension(received.clone()));
+        let addr = SocketAddr::from(([0, 0, 0, 0], port));
+        tokio::spawn(async move {
+            let _ = axum::Server::bind(&addr)
+                .serve(app.into_make_service())
+                .await;
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        received
+    }
+}
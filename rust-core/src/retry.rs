@@ -0,0 +1,126 @@
+use rand::Rng;
+use std::time::Duration;
+
+// floor of the base interval, a zero interval would retry in a tight loop
+const MIN_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Delay grows by `multiplier` on each consecutive failure
+    pub multiplier: f64,
+    /// Upper bound of the delay, not applied to server Retry-After
+    pub max_delay: Duration,
+    /// Randomize the delay by up to `jitter_percent` percent in both directions
+    pub jitter_percent: u8,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            multiplier: 2.0,
+            max_delay: Duration::from_secs(300),
+            jitter_percent: 20,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay before next sync, `failures` is reset to 0 after a success.
+    pub fn delay(
+        &self,
+        interval: Duration,
+        failures: u32,
+        retry_after: Option<Duration>,
+    ) -> Duration {
+        let interval = interval.max(MIN_INTERVAL);
+        let max_delay = self.max_delay.max(interval);
+        let factor = self.multiplier.max(1.0).powi(failures.min(32) as i32);
+        // clamp in seconds, `Duration::mul_f64` panics on overflow
+        let secs = (interval.as_secs_f64() * factor).min(max_delay.as_secs_f64());
+        let delay = Duration::try_from_secs_f64(secs).unwrap_or(max_delay);
+        let delay = self.jitter(delay);
+        match retry_after {
+            Some(retry_after) => delay.max(retry_after),
+            None => delay,
+        }
+    }
+
+    fn jitter(&self, delay: Duration) -> Duration {
+        let percent = self.jitter_percent.min(100) as f64;
+        if percent == 0.0 {
+            return delay;
+        }
+        let ratio = rand::thread_rng().gen_range(-percent..=percent) / 100.0;
+        Duration::try_from_secs_f64(delay.as_secs_f64() * (1.0 + ratio)).unwrap_or(delay)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        let policy = RetryPolicy {
+            multiplier: 2.0,
+            max_delay: Duration::from_secs(10),
+            jitter_percent: 0,
+        };
+        let interval = Duration::from_secs(1);
+        assert_eq!(policy.delay(interval, 0, None), Duration::from_secs(1));
+        assert_eq!(policy.delay(interval, 1, None), Duration::from_secs(2));
+        assert_eq!(policy.delay(interval, 3, None), Duration::from_secs(8));
+        assert_eq!(policy.delay(interval, 4, None), Duration::from_secs(10));
+        assert_eq!(policy.delay(interval, 100, None), Duration::from_secs(10));
+        assert_eq!(
+            policy.delay(interval, 1, Some(Duration::from_secs(60))),
+            Duration::from_secs(60)
+        );
+    }
+
+    #[test]
+    fn test_zero_interval() {
+        let policy = RetryPolicy {
+            jitter_percent: 0,
+            ..Default::default()
+        };
+        assert_eq!(policy.delay(Duration::ZERO, 0, None), MIN_INTERVAL);
+        assert_eq!(policy.delay(Duration::ZERO, 2, None), MIN_INTERVAL * 4);
+        let policy = RetryPolicy {
+            max_delay: Duration::ZERO,
+            ..policy
+        };
+        assert_eq!(policy.delay(Duration::ZERO, 2, None), MIN_INTERVAL);
+    }
+
+    #[test]
+    fn test_backoff_overflow() {
+        let policy = RetryPolicy {
+            multiplier: 1e20,
+            max_delay: Duration::from_secs(300),
+            jitter_percent: 0,
+        };
+        let interval = Duration::from_secs(1);
+        assert_eq!(policy.delay(interval, u32::MAX, None), policy.max_delay);
+        let policy = RetryPolicy {
+            multiplier: f64::INFINITY,
+            jitter_percent: 20,
+            ..policy
+        };
+        assert!(policy.delay(interval, u32::MAX, None) <= Duration::from_secs(360));
+    }
+
+    #[test]
+    fn test_jitter() {
+        let policy = RetryPolicy {
+            jitter_percent: 50,
+            ..Default::default()
+        };
+        let interval = Duration::from_secs(10);
+        for _ in 0..100 {
+            let delay = policy.delay(interval, 0, None);
+            assert!(delay >= Duration::from_secs(5));
+            assert!(delay <= Duration::from_secs(15));
+        }
+    }
+}
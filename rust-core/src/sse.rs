@@ -0,0 +1,194 @@
+use crate::sync::{http_error, status_error};
+use crate::FPError;
+use headers::HeaderValue;
+use reqwest::{
+    header::{ACCEPT, AUTHORIZATION, CACHE_CONTROL, USER_AGENT},
+    Client, Method, Response,
+};
+use std::{collections::VecDeque, time::Duration};
+use tracing::{debug, trace, warn};
+use url::Url;
+
+/// An event of a Server-Sent Events stream
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SseEvent {
+    pub event: String,
+    pub data: String,
+}
+
+// the shared client has a total request timeout, which would end the stream
+pub fn client(connect_timeout: Duration) -> Result<Client, FPError> {
+    Client::builder()
+        .connect_timeout(connect_timeout)
+        .build()
+        .map_err(http_error)
+}
+
+/// Open the stream, `timeout` applies until response headers are received.
+pub async fn connect(
+    client: &Client,
+    url: Url,
+    auth: HeaderValue,
+    timeout: Duration,
+) -> Result<EventStream, FPError> {
+    let request = client
+        .request(Method::GET, url)
+        .header(AUTHORIZATION, auth)
+        .header(USER_AGENT, &*crate::USER_AGENT)
+        .header(ACCEPT, "text/event-stream")
+        .header(CACHE_CONTROL, "no-cache");
+
+    trace!("sse connect {:?}", request);
+    let resp = tokio::time::timeout(timeout, request.send())
+        .await
+        .map_err(|_| FPError::Timeout)?
+        .map_err(http_error)?;
+    let status = resp.status();
+    if !status.is_success() {
+        return Err(status_error(status, resp.headers()));
+    }
+    Ok(EventStream {
+        resp,
+        parser: Default::default(),
+        events: Default::default(),
+    })
+}
+
+#[derive(Debug)]
+pub struct EventStream {
+    resp: Response,
+    parser: EventParser,
+    events: VecDeque<SseEvent>,
+}
+
+impl EventStream {
+    /// Next event, `None` if the stream ended, failed or nothing was received
+    /// within `idle_timeout`. Servers are expected to send comments as heartbeat.
+    pub async fn next(&mut self, idle_timeout: Duration) -> Option<SseEvent> {
+        loop {
+            if let Some(event) = self.events.pop_front() {
+                return Some(event);
+            }
+            match tokio::time::timeout(idle_timeout, self.resp.chunk()).await {
+                Ok(Ok(Some(chunk))) => self.events.extend(self.parser.feed(&chunk)),
+                Ok(Ok(None)) => {
+                    debug!("sse stream ended");
+                    return None;
+                }
+                Ok(Err(e)) => {
+                    warn!("sse stream error: {}", e);
+                    return None;
+                }
+                Err(_) => {
+                    warn!("sse stream idle for {:?}", idle_timeout);
+                    return None;
+                }
+            }
+        }
+    }
+}
+
+// incremental parser of the `text/event-stream` format, `id` and `retry`
+// fields are not used
+#[derive(Debug, Default)]
+struct EventParser {
+    line: Vec<u8>,
+    event: Option<String>,
+    data: Option<String>,
+}
+
+impl EventParser {
+    fn feed(&mut self, chunk: &[u8]) -> Vec<SseEvent> {
+        let mut events = vec![];
+        for b in chunk {
+            if *b != b'\n' {
+                self.line.push(*b);
+                continue;
+            }
+            let mut line = std::mem::take(&mut self.line);
+            if line.last() == Some(&b'\r') {
+                line.pop();
+            }
+            if let Some(event) = self.parse_line(&String::from_utf8_lossy(&line)) {
+                events.push(event);
+            }
+        }
+        events
+    }
+
+    fn parse_line(&mut self, line: &str) -> Option<SseEvent> {
+        if line.is_empty() {
+            if self.event.is_none() && self.data.is_none() {
+                return None;
+            }
+            return Some(SseEvent {
+                event: self.event.take().unwrap_or_else(|| "message".to_owned()),
+                data: self.data.take().unwrap_or_default(),
+            });
+        }
+        if line.starts_with(':') {
+            return None;
+        }
+        let (field, value) = match line.split_once(':') {
+            Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+            None => (line, ""),
+        };
+        match field {
+            "event" => self.event = Some(value.to_owned()),
+            "data" => match &mut self.data {
+                Some(data) => {
+                    data.push('\n');
+                    data.push_str(value);
+                }
+                None => self.data = Some(value.to_owned()),
+            },
+            _ => {}
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_connect_timeout() {
+        // accepts connections but never responds
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:19027")
+            .await
+            .unwrap();
+        tokio::spawn(async move {
+            let mut sockets = vec![];
+            while let Ok((socket, _)) = listener.accept().await {
+                sockets.push(socket);
+            }
+        });
+        let client = client(Duration::from_secs(1)).unwrap();
+        let url = "http://127.0.0.1:19027/realtime".parse().unwrap();
+        let auth = HeaderValue::from_static("client-sdk-key");
+        let result = connect(&client, url, auth, Duration::from_millis(100)).await;
+        assert!(matches!(result, Err(FPError::Timeout)));
+    }
+
+    #[test]
+    fn test_parse_events() {
+        let mut parser = EventParser::default();
+        assert!(parser.feed(b": heartbeat\n\n").is_empty());
+        assert!(parser.feed(b"event: upd").is_empty());
+        let events = parser.feed(b"ate\r\ndata: {\"version\"\ndata: :1}\r\n\r\ndata:x\n\n");
+        assert_eq!(
+            events,
+            vec![
+                SseEvent {
+                    event: "update".to_owned(),
+                    data: "{\"version\"\n:1}".to_owned(),
+                },
+                SseEvent {
+                    event: "message".to_owned(),
+                    data: "x".to_owned(),
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,185 @@
+use crate::{FPDetail, Repository};
+use parking_lot::RwLock;
+use serde_json::Value;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+pub type SubscriberId = u64;
+
+type Callback = Arc<dyn Fn(&ToggleChange) + Send + Sync>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ToggleChange {
+    pub key: String,
+    pub old: Option<FPDetail<Value>>,
+    pub new: Option<FPDetail<Value>>,
+}
+
+#[derive(Clone, Default)]
+pub struct Subscribers {
+    inner: Arc<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    next_id: AtomicU64,
+    subscribers: RwLock<Vec<Subscriber>>,
+}
+
+struct Subscriber {
+    id: SubscriberId,
+    toggle: Option<String>,
+    callback: Callback,
+}
+
+impl Subscribers {
+    pub fn subscribe(&self, toggle: Option<String>, callback: Callback) -> SubscriberId {
+        let id = self.inner.next_id.fetch_add(1, Ordering::Relaxed);
+        let mut subscribers = self.inner.subscribers.write();
+        subscribers.push(Subscriber {
+            id,
+            toggle,
+            callback,
+        });
+        id
+    }
+
+    pub fn unsubscribe(&self, id: SubscriberId) -> bool {
+        let mut subscribers = self.inner.subscribers.write();
+        let len = subscribers.len();
+        subscribers.retain(|s| s.id != id);
+        subscribers.len() != len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inner.subscribers.read().is_empty()
+    }
+
+    pub fn notify(&self, changes: &[ToggleChange]) {
+        for change in changes {
+            // collect first, callbacks may subscribe or unsubscribe
+            let callbacks: Vec<Callback> = {
+                let subscribers = self.inner.subscribers.read();
+                subscribers
+                    .iter()
+                    .filter(|s| match &s.toggle {
+                        None => true,
+                        Some(t) => t == &change.key,
+                    })
+                    .map(|s| s.callback.clone())
+                    .collect()
+            };
+            for callback in callbacks {
+                callback(change);
+            }
+        }
+    }
+}
+
+pub fn diff(old: &Repository, new: &Repository) -> Vec<ToggleChange> {
+    let mut changes = vec![];
+    for (key, new_detail) in new {
+        let old_detail = old.get(key);
+        if old_detail.is_none_or(|o| is_changed(o, new_detail)) {
+            changes.push(ToggleChange {
+                key: key.clone(),
+                old: old_detail.cloned(),
+                new: Some(new_detail.clone()),
+            });
+        }
+    }
+    for (key, old_detail) in old {
+        if !new.contains_key(key) {
+            changes.push(ToggleChange {
+                key: key.clone(),
+                old: Some(old_detail.clone()),
+                new: None,
+            });
+        }
+    }
+    changes
+}
+
+fn is_changed(old: &FPDetail<Value>, new: &FPDetail<Value>) -> bool {
+    old.value != new.value
+        || old.variation_index != new.variation_index
+        || old.version != new.version
+}
+
+impl std::fmt::Debug for Subscribers {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Subscribers")
+            .field("len", &self.inner.subscribers.read().len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parking_lot::Mutex;
+    use serde_json::json;
+
+    fn detail(value: Value, version: u64) -> FPDetail<Value> {
+        FPDetail {
+            value,
+            version: Some(version),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_diff() {
+        let mut old = Repository::default();
+        old.insert("same".to_owned(), detail(json!(true), 1));
+        old.insert("changed".to_owned(), detail(json!(1), 1));
+        old.insert("removed".to_owned(), detail(json!("a"), 1));
+        let mut new = Repository::default();
+        new.insert("same".to_owned(), detail(json!(true), 1));
+        new.insert("changed".to_owned(), detail(json!(2), 2));
+        new.insert("added".to_owned(), detail(json!("b"), 1));
+
+        let mut changes = diff(&old, &new);
+        changes.sort_by(|a, b| a.key.cmp(&b.key));
+        let keys: Vec<&str> = changes.iter().map(|c| c.key.as_str()).collect();
+        assert_eq!(keys, vec!["added", "changed", "removed"]);
+        assert_eq!(changes[0].old, None);
+        assert_eq!(changes[1].new, Some(detail(json!(2), 2)));
+        assert_eq!(changes[2].new, None);
+    }
+
+    #[test]
+    fn test_notify() {
+        let subscribers = Subscribers::default();
+        let all = Arc::new(Mutex::new(vec![]));
+        let one = Arc::new(Mutex::new(vec![]));
+
+        let all_clone = all.clone();
+        subscribers.subscribe(
+            None,
+            Arc::new(move |c: &ToggleChange| all_clone.lock().push(c.key.clone())),
+        );
+        let one_clone = one.clone();
+        let id = subscribers.subscribe(
+            Some("a".to_owned()),
+            Arc::new(move |c: &ToggleChange| one_clone.lock().push(c.key.clone())),
+        );
+
+        let change = |key: &str| ToggleChange {
+            key: key.to_owned(),
+            old: None,
+            new: Some(detail(json!(true), 1)),
+        };
+        subscribers.notify(&[change("a"), change("b")]);
+        assert_eq!(*all.lock(), vec!["a", "b"]);
+        assert_eq!(*one.lock(), vec!["a"]);
+
+        assert!(subscribers.unsubscribe(id));
+        assert!(!subscribers.unsubscribe(id));
+        subscribers.notify(&[change("a")]);
+        assert_eq!(one.lock().len(), 1);
+        assert_eq!(all.lock().len(), 3);
+    }
+}
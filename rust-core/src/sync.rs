@@ -1,14 +1,25 @@
+use crate::cache::Cache;
+use crate::retry::RetryPolicy;
+use crate::subscriber::{diff, Subscribers, ToggleChange};
 use crate::{FPDetail, FPError, Repository};
 use headers::HeaderValue;
 use http::StatusCode;
-use parking_lot::RwLock;
-use reqwest::{header::AUTHORIZATION, header::USER_AGENT, Client, Method};
+use parking_lot::{Mutex, RwLock};
+use reqwest::{
+    header::{
+        HeaderMap, AUTHORIZATION, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED,
+        RETRY_AFTER, USER_AGENT,
+    },
+    Client, Method,
+};
+use serde::Deserialize;
 use serde_json::Value;
 use std::{
     collections::HashMap,
     sync::{mpsc::sync_channel, Arc},
-    time::{Duration, Instant},
+    time::{Duration, Instant, SystemTime},
 };
+use tokio::{sync::watch, task::JoinHandle};
 use tracing::{debug, error, trace};
 use url::Url;
 
@@ -17,23 +28,74 @@ pub struct Synchronizer {
     inner: Arc<Inner>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum SyncType {
     Realtime,
     Polling,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FPState {
+    /// No sync finished yet
+    Initializing,
+    /// Last sync succeeded
+    Ready,
+    /// Last sync failed, toggles from previous sync or cache are served
+    Stale,
+    /// Last sync failed and no toggles are available
+    Failed,
+}
+
+/// Payload of a realtime `update` notification. `toggles` are the changed
+/// toggles evaluated for `user`, `version` is the version of the server
+/// repository they belong to. Both are optional, a notification without them
+/// only tells that toggles should be fetched again.
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct UpdatePayload {
+    pub version: Option<u64>,
+    pub user: Option<String>,
+    pub toggles: Option<Repository>,
+}
+
 #[derive(Debug)]
 struct Inner {
-    remote_url: Url,
+    remote_url: RwLock<Url>,
     refresh_interval: Duration,
     auth: HeaderValue,
     client: Client,
     repo: Arc<RwLock<Repository>>,
     should_stop: Arc<RwLock<bool>>,
+    subscribers: Subscribers,
+    cache: RwLock<Option<Cache>>,
+    state: watch::Sender<FPState>,
+    last_error: RwLock<Option<FPError>>,
+    last_synced_at: RwLock<Option<SystemTime>>,
+    validators: RwLock<Validators>,
+    // server repository version the toggles are known to be up to date with
+    version: RwLock<Option<u64>>,
+    task: Mutex<Option<JoinHandle<()>>>,
+    // result of the refresh in flight, shared by concurrent callers
+    refreshing: Mutex<Option<watch::Receiver<Option<RefreshResult>>>>,
+}
+
+type RefreshResult = Result<bool, FPError>;
+
+// response validators of the current repo, for conditional requests
+#[derive(Debug, Default, Clone)]
+struct Validators {
+    etag: Option<HeaderValue>,
+    last_modified: Option<HeaderValue>,
+}
+
+impl Validators {
+    fn from_headers(headers: &HeaderMap) -> Self {
+        Self {
+            etag: headers.get(ETAG).cloned(),
+            last_modified: headers.get(LAST_MODIFIED).cloned(),
+        }
+    }
 }
 
-//TODO: graceful shutdown
 impl Synchronizer {
     pub fn new(
         remote_url: Url,
@@ -42,47 +104,137 @@ impl Synchronizer {
         repo: Arc<RwLock<Repository>>,
         should_stop: Arc<RwLock<bool>>,
         client: Client,
+        subscribers: Subscribers,
     ) -> Self {
         Self {
             inner: Arc::new(Inner {
-                remote_url,
+                remote_url: RwLock::new(remote_url),
                 refresh_interval,
                 auth,
                 client,
                 repo,
                 should_stop,
+                subscribers,
+                cache: Default::default(),
+                state: watch::channel(FPState::Initializing).0,
+                last_error: Default::default(),
+                last_synced_at: Default::default(),
+                validators: Default::default(),
+                version: Default::default(),
+                task: Default::default(),
+                refreshing: Default::default(),
             }),
         }
     }
 
-    pub fn start_sync(&self, start_wait: Option<Duration>) {
+    pub fn set_cache(&self, cache: Option<Cache>) {
+        let mut guard = self.inner.cache.write();
+        *guard = cache;
+    }
+
+    // switch to another user, toggles of the previous user are replaced by `repo`
+    pub fn reset(&self, remote_url: Url, cache: Option<Cache>, repo: Repository) {
+        *self.inner.remote_url.write() = remote_url;
+        self.set_cache(cache);
+        *self.inner.validators.write() = Default::default();
+        *self.inner.version.write() = None;
+        *self.inner.last_synced_at.write() = None;
+        self.inner.state.send_replace(FPState::Initializing);
+        let changes = self.inner.swap_repo(repo);
+        self.inner.subscribers.notify(&changes);
+    }
+
+    pub fn state(&self) -> FPState {
+        *self.inner.state.borrow()
+    }
+
+    pub fn last_error(&self) -> Option<FPError> {
+        self.inner.last_error.read().clone()
+    }
+
+    /// Time of the last successful sync, including not modified responses
+    pub fn last_synced_at(&self) -> Option<SystemTime> {
+        *self.inner.last_synced_at.read()
+    }
+
+    pub async fn wait_until_ready(&self, timeout: Duration) -> Result<(), FPError> {
+        let mut rx = self.inner.state.subscribe();
+        let wait = async {
+            loop {
+                let state = *rx.borrow_and_update();
+                match state {
+                    FPState::Ready | FPState::Stale => return Ok(()),
+                    // retry will not help with a bad sdk key
+                    FPState::Failed => {
+                        if let Some(e @ FPError::Unauthorized { .. }) = self.last_error() {
+                            return Err(e);
+                        }
+                    }
+                    FPState::Initializing => {}
+                }
+                if rx.changed().await.is_err() {
+                    return Ok(());
+                }
+            }
+        };
+        match tokio::time::timeout(timeout, wait).await {
+            Ok(r) => r,
+            Err(_) => Err(self.last_error().unwrap_or(FPError::Timeout)),
+        }
+    }
+
+    /// Sync until stopped, or only until the first success without `polling`.
+    pub fn start_sync(
+        &self,
+        start_wait: Option<Duration>,
+        retry_policy: RetryPolicy,
+        polling: bool,
+    ) -> Result<(), FPError> {
         let should_stop = self.inner.should_stop.clone();
         let inner = self.inner.clone();
         let (tx, rx) = sync_channel(1);
         let start = Instant::now();
         let mut is_send = false;
-        let interval_duration = inner.refresh_interval;
-        let is_timeout = Self::init_timeout_fn(start_wait, interval_duration, start);
+        let is_timeout = Self::init_timeout_fn(start_wait, start);
 
-        tokio::spawn(async move {
-            let mut interval = tokio::time::interval(inner.refresh_interval);
+        let task = tokio::spawn(async move {
+            let mut failures = 0;
             loop {
                 let result = inner.sync_now(SyncType::Polling).await;
+                let synced = result.is_ok();
+                let retry_after = match &result {
+                    Ok(_) => {
+                        failures = 0;
+                        None
+                    }
+                    Err(e) => {
+                        failures += 1;
+                        e.retry_after()
+                    }
+                };
+                let delay = retry_policy.delay(inner.refresh_interval, failures, retry_after);
 
-                if let Some(r) = Self::should_send(result, &is_timeout, is_send) {
+                if let Some(r) = Self::should_send(result, &is_timeout, is_send, delay) {
                     is_send = true;
                     let _ = tx.try_send(r);
                 }
 
-                if *should_stop.read() {
+                if *should_stop.read() || (synced && !polling) {
                     break;
                 }
-                interval.tick().await;
+                trace!("next sync after {:?}", delay);
+                tokio::time::sleep(delay).await;
             }
         });
+        if let Some(previous) = self.inner.task.lock().replace(task) {
+            previous.abort();
+        }
 
-        if start_wait.is_some() {
-            let _ = rx.recv();
+        match start_wait {
+            Some(_) => rx
+                .recv()
+                .unwrap_or_else(|_| Err(FPError::HttpError("sync stopped".to_owned()))),
+            None => Ok(()),
         }
     }
 
@@ -90,33 +242,114 @@ impl Synchronizer {
         self.inner.sync_now(t).await
     }
 
+    /// Fetch toggles now and return whether they changed. Concurrent calls
+    /// share one request, which is finished even if the caller is dropped.
+    pub async fn refresh(&self) -> Result<bool, FPError> {
+        let mut rx = {
+            let mut refreshing = self.inner.refreshing.lock();
+            match &*refreshing {
+                Some(rx) => rx.clone(),
+                None => {
+                    let (tx, rx) = watch::channel(None);
+                    *refreshing = Some(rx.clone());
+                    let inner = self.inner.clone();
+                    tokio::spawn(async move {
+                        let before = inner.repo.read().clone();
+                        let result = inner
+                            .sync_now(SyncType::Polling)
+                            .await
+                            .map(|_| *inner.repo.read() != before);
+                        *inner.refreshing.lock() = None;
+                        tx.send_replace(Some(result));
+                    });
+                    rx
+                }
+            }
+        };
+        loop {
+            if let Some(result) = rx.borrow_and_update().clone() {
+                return result;
+            }
+            if rx.changed().await.is_err() {
+                return Err(FPError::HttpError("refresh cancelled".to_owned()));
+            }
+        }
+    }
+
+    /// Apply a realtime update without a fetch when possible: an already
+    /// synced `version` is skipped, and `toggles` are merged if `toggles_url`
+    /// is given, i.e. they were evaluated for the user of this synchronizer.
+    /// Otherwise toggles are fetched again.
+    pub async fn apply_update(
+        &self,
+        update: &UpdatePayload,
+        toggles_url: Option<&Url>,
+    ) -> Result<(), FPError> {
+        if let Some(version) = update.version {
+            if self.inner.version.read().is_some_and(|v| version <= v) {
+                trace!("skip update of synced version {}", version);
+                return Ok(());
+            }
+        }
+        if let (Some(toggles), Some(url)) = (&update.toggles, toggles_url) {
+            if self.inner.merge_repo(url, toggles, update.version) {
+                return Ok(());
+            }
+        }
+        let remote_url = self.inner.remote_url.read().clone();
+        self.inner.sync_now(SyncType::Realtime).await?;
+        if let Some(version) = update.version {
+            self.inner.set_version(&remote_url, version);
+        }
+        Ok(())
+    }
+
+    /// Stop polling at once, a request in flight is cancelled.
+    pub fn stop(&self) {
+        *self.inner.should_stop.write() = true;
+        self.pause();
+    }
+
+    /// Same as `stop`, but polling can be restarted by `start_sync`.
+    pub fn pause(&self) {
+        if let Some(task) = self.inner.task.lock().take() {
+            task.abort();
+        }
+    }
+
     #[cfg(test)]
     pub fn repository(&self) -> Arc<RwLock<Repository>> {
         self.inner.repo.clone()
     }
 
+    // whether start_wait will be exceeded before the next sync
     fn init_timeout_fn(
         start_wait: Option<Duration>,
-        interval: Duration,
         start: Instant,
-    ) -> Option<Box<dyn Fn() -> bool + Send>> {
+    ) -> Option<Box<dyn Fn(Duration) -> bool + Send>> {
         match start_wait {
-            Some(timeout) => Some(Box::new(move || start.elapsed() + interval > timeout)),
+            Some(timeout) => Some(Box::new(move |delay| start.elapsed() + delay > timeout)),
             None => None,
         }
     }
 
     fn should_send(
         result: Result<(), FPError>,
-        is_timeout: &Option<Box<dyn Fn() -> bool + Send>>,
+        is_timeout: &Option<Box<dyn Fn(Duration) -> bool + Send>>,
         is_send: bool,
+        delay: Duration,
     ) -> Option<Result<(), FPError>> {
         if let Some(is_timeout) = is_timeout {
             match result {
                 Ok(_) if !is_send => {
                     return Some(Ok(()));
                 }
-                Err(e) if !is_send && is_timeout() => {
+                // retrying will not help, fail without waiting for start_wait
+                Err(e @ FPError::Unauthorized { .. }) if !is_send => {
+                    error!("sync error: {}", e);
+                    return Some(Err(e));
+                }
+                Err(e) if !is_send && is_timeout(delay) => {
                     error!("sync error: {}", e);
                     return Some(Err(e));
                 }
@@ -130,46 +363,173 @@ impl Synchronizer {
 
 impl Inner {
     pub async fn sync_now(&self, t: SyncType) -> Result<(), FPError> {
-        let request = self
+        let remote_url = self.remote_url.read().clone();
+        let result = self.do_sync(&remote_url, t).await;
+        if *self.remote_url.read() != remote_url {
+            // user switched, the result is not for the current user
+            return result;
+        }
+        match &result {
+            Ok(_) => {
+                *self.last_error.write() = None;
+                *self.last_synced_at.write() = Some(SystemTime::now());
+                self.state.send_replace(FPState::Ready);
+            }
+            Err(e) => {
+                *self.last_error.write() = Some(e.clone());
+                let state = if self.repo.read().is_empty() {
+                    FPState::Failed
+                } else {
+                    FPState::Stale
+                };
+                self.state.send_replace(state);
+            }
+        }
+        result
+    }
+
+    async fn do_sync(&self, remote_url: &Url, t: SyncType) -> Result<(), FPError> {
+        let mut request = self
             .client
-            .request(Method::GET, self.remote_url.clone())
+            .request(Method::GET, remote_url.clone())
             .header(AUTHORIZATION, self.auth.clone())
-            .header(USER_AGENT, &*crate::USER_AGENT)
-            .timeout(self.refresh_interval);
+            .header(USER_AGENT, &*crate::USER_AGENT);
+
+        let validators = self.validators.read().clone();
+        if let Some(etag) = validators.etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = validators.last_modified {
+            request = request.header(IF_MODIFIED_SINCE, last_modified);
+        }
 
         trace!("sync_now {:?} {:?}", self.auth, t);
 
         //TODO: report failure
-        match request.send().await {
-            Err(e) => Err(FPError::HttpError(e.to_string())),
-            Ok(resp) => {
-                let status = resp.status();
-                match status {
-                    StatusCode::OK => match resp.text().await {
-                        Err(e) => Err(FPError::HttpError(e.to_string())),
-                        Ok(body) => {
-                            debug!("sync body {:?}", body);
-                            match serde_json::from_str::<HashMap<String, FPDetail<Value>>>(&body) {
-                                Err(e) => Err(FPError::JsonError(e.to_string())),
-                                Ok(r) => {
-                                    // TODO: validate repo
-                                    // TODO: diff change, notify subscriber
-                                    debug!("sync success {:?}", r);
-                                    let mut repo = self.repo.write();
-                                    *repo = r;
-                                    Ok(())
-                                }
-                            }
-                        }
-                    },
-                    _ => Err(FPError::HttpError(format!(
-                        "sync http failed: status code {}",
-                        status
-                    ))),
+        let resp = request.send().await.map_err(http_error)?;
+        let status = resp.status();
+        match status {
+            StatusCode::OK => {
+                let validators = Validators::from_headers(resp.headers());
+                let body = resp.text().await.map_err(http_error)?;
+                debug!("sync body {:?}", body);
+                let r = serde_json::from_str::<HashMap<String, FPDetail<Value>>>(&body)
+                    .map_err(|e| FPError::InvalidPayload(e.to_string()))?;
+                // TODO: validate repo
+                debug!("sync success {:?}", r);
+                self.update_repo(remote_url, r, validators);
+                Ok(())
+            }
+            StatusCode::NOT_MODIFIED => {
+                debug!("sync not modified");
+                Ok(())
+            }
+            _ => Err(status_error(status, resp.headers())),
+        }
+    }
+
+    fn update_repo(&self, remote_url: &Url, r: Repository, validators: Validators) {
+        let changes = {
+            // hold the url lock, user can not be switched while updating
+            let current_url = self.remote_url.read();
+            if *current_url != *remote_url {
+                debug!("user switched, discard toggles of previous user");
+                return;
+            }
+            if let Some(cache) = &*self.cache.read() {
+                if let Err(e) = cache.store(&r) {
+                    error!("store cache error: {}", e);
+                }
+            }
+            *self.validators.write() = validators;
+            self.swap_repo(r)
+        };
+        // notify outside the lock, subscribers may read toggles or switch user
+        self.subscribers.notify(&changes);
+    }
+
+    // merge changed toggles into a synced repo, false if the repo is not
+    // synced yet or `remote_url` is not the current user
+    fn merge_repo(&self, remote_url: &Url, toggles: &Repository, version: Option<u64>) -> bool {
+        let changes = {
+            let current_url = self.remote_url.read();
+            if *current_url != *remote_url || self.last_synced_at.read().is_none() {
+                return false;
+            }
+            let mut r = self.repo.read().clone();
+            r.extend(toggles.iter().map(|(k, v)| (k.clone(), v.clone())));
+            if let Some(cache) = &*self.cache.read() {
+                if let Err(e) = cache.store(&r) {
+                    error!("store cache error: {}", e);
                 }
             }
+            // the repo no longer matches the last response
+            *self.validators.write() = Default::default();
+            if let Some(version) = version {
+                *self.version.write() = Some(version);
+            }
+            debug!("merge {} toggles of realtime update", toggles.len());
+            self.swap_repo(r)
+        };
+        self.subscribers.notify(&changes);
+        true
+    }
+
+    fn set_version(&self, remote_url: &Url, version: u64) {
+        let current_url = self.remote_url.read();
+        if *current_url == *remote_url {
+            let mut v = self.version.write();
+            *v = Some(v.map_or(version, |v| v.max(version)));
         }
     }
+
+    fn swap_repo(&self, r: Repository) -> Vec<ToggleChange> {
+        let mut repo = self.repo.write();
+        let changes = if self.subscribers.is_empty() {
+            vec![]
+        } else {
+            diff(&repo, &r)
+        };
+        *repo = r;
+        changes
+    }
+}
+
+pub(crate) fn status_error(status: StatusCode, headers: &HeaderMap) -> FPError {
+    match status {
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => FPError::Unauthorized {
+            status: status.as_u16(),
+        },
+        StatusCode::TOO_MANY_REQUESTS => FPError::RateLimited {
+            retry_after: retry_after(headers),
+        },
+        s if s.is_server_error() => FPError::ServerError {
+            status: status.as_u16(),
+            retry_after: retry_after(headers),
+        },
+        s if s.is_client_error() => FPError::ClientError {
+            status: status.as_u16(),
+        },
+        _ => FPError::HttpError(format!("http failed: status code {}", status)),
+    }
+}
+
+pub(crate) fn http_error(e: reqwest::Error) -> FPError {
+    if e.is_timeout() {
+        FPError::Timeout
+    } else {
+        FPError::HttpError(e.to_string())
+    }
+}
+
+// Retry-After is either delay seconds or an http date
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(date.duration_since(SystemTime::now()).unwrap_or_default())
 }
 
 #[cfg(test)]
@@ -189,7 +549,12 @@ mod tests {
         ServerConfig,
     };
     use http::{header, StatusCode};
-    use std::{fs, net::SocketAddr, path::PathBuf};
+    use std::{
+        fs,
+        net::SocketAddr,
+        path::PathBuf,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
 
     #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
     async fn test_sync() {
@@ -206,42 +571,293 @@ mod tests {
         )
         .await;
         let syncer = build_synchronizer(server_port);
-        syncer.start_sync(Some(Duration::from_secs(5)));
+        assert_eq!(syncer.state(), FPState::Initializing);
+        let result = syncer.start_sync(Some(Duration::from_secs(5)), Default::default(), true);
+        assert!(result.is_ok());
+        assert_eq!(syncer.state(), FPState::Ready);
 
         tokio::time::sleep(Duration::from_millis(200)).await;
         let repo = syncer.repository();
         let repo = repo.read();
-        assert!(repo.len() > 0)
+        assert!(!repo.is_empty())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_sync_errors() {
+        let port = 19020;
+        setup_error_api(port).await;
+        let sync_error = |path: &'static str| async move {
+            let url = Url::parse(&format!("http://127.0.0.1:{}/{}", port, path)).unwrap();
+            let syncer = build_synchronizer_with_url(url);
+            syncer.sync_now(SyncType::Polling).await.unwrap_err()
+        };
+
+        assert!(matches!(
+            sync_error("unauthorized").await,
+            FPError::Unauthorized { status: 401 }
+        ));
+        assert!(matches!(
+            sync_error("not_found").await,
+            FPError::ClientError { status: 404 }
+        ));
+        assert!(matches!(
+            sync_error("server_error").await,
+            FPError::ServerError { status: 500, .. }
+        ));
+        assert!(matches!(
+            sync_error("invalid").await,
+            FPError::InvalidPayload(_)
+        ));
+        match sync_error("rate_limited").await {
+            FPError::RateLimited { retry_after } => {
+                assert_eq!(retry_after, Some(Duration::from_secs(30)))
+            }
+            e => panic!("unexpected error {:?}", e),
+        }
+
+        let url = Url::parse(&format!("http://127.0.0.1:{}/slow", port)).unwrap();
+        let mut syncer = build_synchronizer_with_url(url);
+        Arc::get_mut(&mut syncer.inner).unwrap().client = Client::builder()
+            .timeout(Duration::from_millis(100))
+            .build()
+            .unwrap();
+        assert!(matches!(
+            syncer.sync_now(SyncType::Polling).await,
+            Err(FPError::Timeout)
+        ));
+
+        // an invalid sdk key does not wait for start_wait
+        let url = Url::parse(&format!("http://127.0.0.1:{}/unauthorized", port)).unwrap();
+        let syncer = build_synchronizer_with_url(url);
+        let start = Instant::now();
+        let result = syncer.start_sync(Some(Duration::from_secs(5)), Default::default(), true);
+        assert!(matches!(result, Err(FPError::Unauthorized { status: 401 })));
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_sync_not_modified() {
+        let port = 19021;
+        let app = Router::new().route("/toggles", get(etag_toggles));
+        serve(port, app).await;
+
+        let url = Url::parse(&format!("http://127.0.0.1:{}/toggles", port)).unwrap();
+        let syncer = build_synchronizer_with_url(url);
+        assert!(syncer.sync_now(SyncType::Polling).await.is_ok());
+        let synced_at = syncer.last_synced_at().unwrap();
+        assert_eq!(NOT_MODIFIED_COUNT.load(Ordering::SeqCst), 0);
+
+        assert!(syncer.sync_now(SyncType::Polling).await.is_ok());
+        assert_eq!(NOT_MODIFIED_COUNT.load(Ordering::SeqCst), 1);
+        assert!(syncer.last_synced_at().unwrap() >= synced_at);
+        assert!(!syncer.repository().read().is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_apply_update() {
+        let port = 19025;
+        let app = Router::new().route("/toggles", get(counted_toggles));
+        serve(port, app).await;
+
+        let url = Url::parse(&format!("http://127.0.0.1:{}/toggles", port)).unwrap();
+        let syncer = build_synchronizer_with_url(url);
+        let user_url = syncer.inner.remote_url.read().clone();
+        let mut toggles = Repository::default();
+        toggles.insert(
+            "pushed_toggle".to_owned(),
+            FPDetail {
+                value: Value::Bool(true),
+                version: Some(2),
+                ..Default::default()
+            },
+        );
+        let update = |version: Option<u64>, toggles: Option<Repository>| UpdatePayload {
+            version,
+            user: Some("123".to_owned()),
+            toggles,
+        };
+
+        // not synced yet, toggles can not be merged
+        let first = update(Some(1), Some(toggles.clone()));
+        assert!(syncer.apply_update(&first, Some(&user_url)).await.is_ok());
+        assert_eq!(FETCH_COUNT.load(Ordering::SeqCst), 1);
+        assert!(!syncer.repository().read().contains_key("pushed_toggle"));
+
+        // version 1 is synced
+        let synced = update(Some(1), None);
+        assert!(syncer.apply_update(&synced, Some(&user_url)).await.is_ok());
+        assert_eq!(FETCH_COUNT.load(Ordering::SeqCst), 1);
+
+        let pushed = update(Some(2), Some(toggles.clone()));
+        assert!(syncer.apply_update(&pushed, Some(&user_url)).await.is_ok());
+        assert_eq!(FETCH_COUNT.load(Ordering::SeqCst), 1);
+        assert!(syncer.repository().read().contains_key("pushed_toggle"));
+
+        // toggles of another user, or no payload
+        let other = update(Some(3), Some(toggles));
+        assert!(syncer.apply_update(&other, None).await.is_ok());
+        assert_eq!(FETCH_COUNT.load(Ordering::SeqCst), 2);
+        let empty = UpdatePayload::default();
+        assert!(syncer.apply_update(&empty, Some(&user_url)).await.is_ok());
+        assert_eq!(FETCH_COUNT.load(Ordering::SeqCst), 3);
+        assert!(!syncer.repository().read().contains_key("pushed_toggle"));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_refresh() {
+        let port = 19026;
+        let app = Router::new().route("/toggles", get(slow_toggles));
+        serve(port, app).await;
+
+        let url = Url::parse(&format!("http://127.0.0.1:{}/toggles", port)).unwrap();
+        let syncer = build_synchronizer_with_url(url);
+        let (r1, r2, r3) = tokio::join!(syncer.refresh(), syncer.refresh(), syncer.refresh());
+        assert_eq!(SLOW_FETCH_COUNT.load(Ordering::SeqCst), 1);
+        assert!(r1.unwrap() && r2.unwrap() && r3.unwrap());
+
+        assert!(!syncer.refresh().await.unwrap());
+        assert_eq!(SLOW_FETCH_COUNT.load(Ordering::SeqCst), 2);
+    }
+
+    static SLOW_FETCH_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+    async fn slow_toggles() -> Response {
+        SLOW_FETCH_COUNT.fetch_add(1, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        (StatusCode::OK, toggles_body()).into_response()
+    }
+
+    static FETCH_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+    async fn counted_toggles() -> Response {
+        FETCH_COUNT.fetch_add(1, Ordering::SeqCst);
+        (StatusCode::OK, toggles_body()).into_response()
+    }
+
+    #[cfg(feature = "compression")]
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_sync_gzip() {
+        let port = 19022;
+        let app = Router::new()
+            .route("/toggles", get(gzip_toggles))
+            .layer(tower_http::compression::CompressionLayer::new());
+        serve(port, app).await;
+
+        let url = Url::parse(&format!("http://127.0.0.1:{}/toggles", port)).unwrap();
+        let syncer = build_synchronizer_with_url(url);
+        assert!(syncer.sync_now(SyncType::Polling).await.is_ok());
+        assert!(GZIP_ACCEPTED.load(Ordering::SeqCst));
+        // the compressed body is decoded into the repository
+        let repo = syncer.repository();
+        let repo = repo.read();
+        assert_eq!(repo["bool_toggle"].value, Value::Bool(true));
+        assert_eq!(repo["number_toggle"].version, Some(1));
+    }
+
+    #[cfg(feature = "compression")]
+    static GZIP_ACCEPTED: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+    #[cfg(feature = "compression")]
+    async fn gzip_toggles(headers: http::HeaderMap) -> Response {
+        let accept_encoding = headers
+            .get(header::ACCEPT_ENCODING)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default();
+        GZIP_ACCEPTED.store(accept_encoding.contains("gzip"), Ordering::SeqCst);
+        let body = toggles_body();
+        (
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, "application/json")],
+            body,
+        )
+            .into_response()
+    }
+
+    static NOT_MODIFIED_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+    async fn etag_toggles(headers: http::HeaderMap) -> Response {
+        if headers.get(header::IF_NONE_MATCH) == Some(&HeaderValue::from_static("\"v1\"")) {
+            NOT_MODIFIED_COUNT.fetch_add(1, Ordering::SeqCst);
+            return StatusCode::NOT_MODIFIED.into_response();
+        }
+        (StatusCode::OK, [(header::ETAG, "\"v1\"")], toggles_body()).into_response()
+    }
+
+    #[test]
+    fn test_retry_after() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(retry_after(&headers), None);
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("120"));
+        assert_eq!(retry_after(&headers), Some(Duration::from_secs(120)));
+        headers.insert(
+            RETRY_AFTER,
+            HeaderValue::from_static("Wed, 21 Oct 2015 07:28:00 GMT"),
+        );
+        assert_eq!(retry_after(&headers), Some(Duration::ZERO));
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("soon"));
+        assert_eq!(retry_after(&headers), None);
     }
 
     fn build_synchronizer(port: u16) -> Synchronizer {
-        let user = FPUser::new("123");
-        let mut remote_url =
+        let remote_url =
             Url::parse(&format!("http://127.0.0.1:{}/api/client-sdk/toggles", port)).unwrap();
+        build_synchronizer_with_url(remote_url)
+    }
+
+    fn build_synchronizer_with_url(mut remote_url: Url) -> Synchronizer {
+        let user = FPUser::new("123");
         remote_url.set_query(Some(&format!("user={}", user.as_base64())));
         let refresh_interval = Duration::from_millis(1000);
-        let auth = SdkAuthorization("client-sdk-key".to_owned()).encode();
+        let auth = SdkAuthorization("client-sdk-key".to_owned())
+            .encode()
+            .unwrap();
         Synchronizer {
             inner: Arc::new(Inner {
-                remote_url,
+                remote_url: RwLock::new(remote_url),
                 refresh_interval,
                 auth,
                 client: Default::default(),
                 repo: Default::default(),
                 should_stop: Default::default(),
+                subscribers: Default::default(),
+                cache: Default::default(),
+                state: watch::channel(FPState::Initializing).0,
+                last_error: Default::default(),
+                last_synced_at: Default::default(),
+                validators: Default::default(),
+                version: Default::default(),
+                task: Default::default(),
+                refreshing: Default::default(),
             }),
         }
     }
 
     async fn setup_mock_api(port: u16) {
         let app = Router::new().route("/api/server-sdk/toggles", get(server_sdk_toggles));
-        let addr = SocketAddr::from(([0, 0, 0, 0], port));
-        tokio::spawn(async move {
-            let _ = axum::Server::bind(&addr)
-                .serve(app.into_make_service())
-                .await;
-        });
-        tokio::time::sleep(Duration::from_millis(100)).await;
+        serve(port, app).await;
+    }
+
+    async fn setup_error_api(port: u16) {
+        let app = Router::new()
+            .route("/unauthorized", get(|| async { StatusCode::UNAUTHORIZED }))
+            .route("/not_found", get(|| async { StatusCode::NOT_FOUND }))
+            .route(
+                "/server_error",
+                get(|| async { StatusCode::INTERNAL_SERVER_ERROR }),
+            )
+            .route(
+                "/rate_limited",
+                get(|| async { (StatusCode::TOO_MANY_REQUESTS, [(header::RETRY_AFTER, "30")]) }),
+            )
+            .route("/invalid", get(|| async { "not json" }))
+            .route(
+                "/slow",
+                get(|| async {
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                    "{}"
+                }),
+            );
+        serve(port, app).await;
     }
 
     async fn setup_fp_server(
@@ -293,9 +909,7 @@ mod tests {
     async fn server_sdk_toggles(
         TypedHeader(SdkAuthorization(_sdk_key)): TypedHeader<SdkAuthorization>,
     ) -> Response {
-        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
-        path.push("resources/fixtures/repo.json");
-        let body = fs::read_to_string(path).unwrap();
+        let body = fixture("repo.json");
         (
             StatusCode::OK,
             [(header::CONTENT_TYPE, "application/json")],
@@ -303,4 +917,25 @@ mod tests {
         )
             .into_response()
     }
+
+    fn toggles_body() -> String {
+        fixture("toggles.json")
+    }
+
+    fn fixture(name: &str) -> String {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/fixtures");
+        path.push(name);
+        fs::read_to_string(path).unwrap()
+    }
+
+    async fn serve(port: u16, app: Router) {
+        let addr = SocketAddr::from(([0, 0, 0, 0], port));
+        tokio::spawn(async move {
+            let _ = axum::Server::bind(&addr)
+                .serve(app.into_make_service())
+                .await;
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
 }
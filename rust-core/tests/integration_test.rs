@@ -4,7 +4,10 @@ use axum::{
     Router, TypedHeader,
 };
 
-use feature_probe_mobile_sdk_core::{FPConfig, FPUser, FeatureProbe, SdkAuthorization, Url};
+use feature_probe_mobile_sdk_core::{
+    ConnectionState, DataSourceMode, FPConfig, FPError, FPState, FPUser, FeatureProbe,
+    SdkAuthorization, Url,
+};
 use feature_probe_server::{
     http::{serve_http, FpHttpHandler},
     realtime::RealtimeSocket,
@@ -41,26 +44,271 @@ async fn integration_test() {
         .unwrap();
 
     let user = FPUser::new("some-user-key");
+    let config = FPConfig {
+        toggles_url,
+        events_url,
+        realtime_url,
+        client_sdk_key: "client-sdk-key".to_owned(),
+        refresh_interval: Duration::from_secs(1),
+        start_wait: Some(Duration::from_secs(3)),
+        ..Default::default()
+    };
+    let fp = FeatureProbe::new(config.clone(), user);
+
+    assert_eq!(fp.state(), FPState::Ready);
+    assert!(fp.bool_value("bool_toggle", false));
+    for _ in 0..30 {
+        if fp.connection_state() == ConnectionState::Connected {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert_eq!(fp.connection_state(), ConnectionState::Connected);
+
+    let detail = fp.bool_detail("bool_toggle", false);
+    assert!(detail.value);
+    assert_eq!(detail.version, Some(1));
+    assert_eq!(detail.rule_index, None);
+    let reason = detail.reason;
+    assert!(reason.contains("default"));
+
+    let result = fp
+        .identify_and_wait(FPUser::new("another-user-key"), Duration::from_secs(3))
+        .await;
+    assert!(result.is_ok());
+    assert!(fp.bool_value("bool_toggle", false));
+
+    let other = fp.add_user(FPUser::new("other-user-key"));
+    let result = other.wait_until_ready(Duration::from_secs(3)).await;
+    assert!(result.is_ok());
+    assert!(other.bool_value("bool_toggle", false));
+    other.close();
+    assert!(!fp.remove_user("other-user-key"));
+    assert_eq!(fp.state(), FPState::Ready);
+
+    // an added user becomes the primary one instead of being synced twice
+    let other = fp.add_user(FPUser::new("other-user-key"));
+    assert!(other.wait_until_ready(Duration::from_secs(3)).await.is_ok());
+    let result = fp
+        .identify_and_wait(FPUser::new("other-user-key"), Duration::from_secs(3))
+        .await;
+    assert!(result.is_ok());
+    assert!(!fp.remove_user("other-user-key"));
+    assert!(fp.bool_value("bool_toggle", false));
+
+    // toggles are already up to date
+    assert!(!fp.refresh().await.unwrap());
+
+    fp.pause();
+    assert!(fp.is_paused());
+    let paused_at = fp.last_synced_at();
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert_eq!(fp.last_synced_at(), paused_at);
+    fp.resume();
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert!(fp.last_synced_at() > paused_at);
+
+    assert!(fp.shutdown(Duration::from_secs(3)).await);
+    assert_eq!(fp.connection_state(), ConnectionState::Disconnected);
+    let synced_at = fp.last_synced_at();
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert_eq!(fp.last_synced_at(), synced_at);
+
+    let polling = FeatureProbe::new(
+        FPConfig {
+            data_source: DataSourceMode::Polling,
+            ..config.clone()
+        },
+        FPUser::new("some-user-key"),
+    );
+    assert_eq!(polling.state(), FPState::Ready);
+    let synced_at = polling.last_synced_at();
+    // polling intervals are at least 1s
+    tokio::time::sleep(Duration::from_millis(1300)).await;
+    assert!(polling.last_synced_at() > synced_at);
+    assert_eq!(polling.connection_state(), ConnectionState::Disconnected);
+    polling.close();
+
+    let manual = FeatureProbe::new(
+        FPConfig {
+            data_source: DataSourceMode::Manual,
+            ..config
+        },
+        FPUser::new("some-user-key"),
+    );
+    assert_eq!(manual.state(), FPState::Ready);
+    let synced_at = manual.last_synced_at();
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert_eq!(manual.last_synced_at(), synced_at);
+    assert_eq!(manual.connection_state(), ConnectionState::Disconnected);
+    manual.close();
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn try_new_failed() {
+    let port = 19013;
+    let toggles_url: Url = format!("http://127.0.0.1:{}/api/client-sdk/toggles", port)
+        .parse()
+        .unwrap();
+    let events_url = format!("http://127.0.0.1:{}/api/events", port)
+        .parse()
+        .unwrap();
+
+    let config = FPConfig {
+        toggles_url: toggles_url.clone(),
+        events_url,
+        realtime_url: toggles_url,
+        client_sdk_key: "client-sdk-key".to_owned(),
+        refresh_interval: Duration::from_millis(100),
+        start_wait: Some(Duration::from_millis(500)),
+        ..Default::default()
+    };
+    let fp = FeatureProbe::try_new(config.clone(), FPUser::new("some-user-key"));
+    assert!(fp.is_err());
+
+    // not a valid header value
+    let invalid_key = FPConfig {
+        client_sdk_key: "client-sdk-key\n".to_owned(),
+        ..config.clone()
+    };
+    let fp = FeatureProbe::try_new(invalid_key, FPUser::new("some-user-key"));
+    assert!(matches!(fp, Err(FPError::HttpError(_))));
+
+    let fp = FeatureProbe::new(
+        FPConfig {
+            start_wait: None,
+            ..config
+        },
+        FPUser::new("some-user-key"),
+    );
+    let result = fp.wait_until_ready(Duration::from_millis(500)).await;
+    assert!(result.is_err());
+    assert_eq!(fp.state(), FPState::Failed);
+    fp.close();
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn realtime_reconnect() {
+    let port = 19014;
+    let realtime_port = 19015;
+    let toggles_url: Url = format!("http://127.0.0.1:{}/api/client-sdk/toggles", port)
+        .parse()
+        .unwrap();
+    let events_url = format!("http://127.0.0.1:{}/api/events", port)
+        .parse()
+        .unwrap();
+    let realtime_url = format!("http://127.0.0.1:{}", realtime_port)
+        .parse()
+        .unwrap();
+
     let fp = FeatureProbe::new(
         FPConfig {
             toggles_url,
             events_url,
             realtime_url,
             client_sdk_key: "client-sdk-key".to_owned(),
-            refresh_interval: Duration::from_millis(100),
+            events_flush_interval: Duration::from_secs(60),
+            http_timeout: Duration::from_secs(1),
+            ..Default::default()
+        },
+        FPUser::new("some-user-key"),
+    );
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert_ne!(fp.connection_state(), ConnectionState::Connected);
+
+    // realtime server comes up after the sdk started
+    let _socket = RealtimeSocket::serve(realtime_port, "/");
+    for _ in 0..50 {
+        if fp.connection_state() == ConnectionState::Connected {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert_eq!(fp.connection_state(), ConnectionState::Connected);
+
+    // closed by a caller outside the runtime, e.g. a plain thread
+    let handle = fp.clone();
+    std::thread::spawn(move || handle.close()).join().unwrap();
+    assert_eq!(fp.connection_state(), ConnectionState::Disconnected);
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    assert_eq!(fp.connection_state(), ConnectionState::Disconnected);
+}
+
+#[cfg(feature = "sse")]
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn realtime_sse() {
+    use feature_probe_mobile_sdk_core::RealtimeTransport;
+
+    let port = 19016;
+    setup_sse_api(port).await;
+    let base_url = format!("http://127.0.0.1:{}", port);
+    let fp = FeatureProbe::new(
+        FPConfig {
+            toggles_url: format!("{}/api/client-sdk/toggles", base_url)
+                .parse()
+                .unwrap(),
+            events_url: format!("{}/api/events", base_url).parse().unwrap(),
+            realtime_url: format!("{}/realtime", base_url).parse().unwrap(),
+            client_sdk_key: "client-sdk-key".to_owned(),
+            events_flush_interval: Duration::from_secs(60),
+            http_timeout: Duration::from_secs(1),
             start_wait: Some(Duration::from_secs(3)),
+            data_source: DataSourceMode::Realtime,
+            realtime_transport: RealtimeTransport::Sse,
+            ..Default::default()
         },
-        user,
+        FPUser::new("some-user-key"),
     );
+    assert_eq!(fp.state(), FPState::Ready);
 
-    assert!(fp.bool_value("bool_toggle", false));
+    // the stand-in pushes `pushed_toggle` right after connecting
+    for _ in 0..30 {
+        if fp.bool_value("pushed_toggle", false) {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert!(fp.bool_value("pushed_toggle", false));
+    assert_eq!(fp.connection_state(), ConnectionState::Connected);
 
-    let detail = fp.bool_detail("bool_toggle", false);
-    assert!(detail.value);
-    assert_eq!(detail.version, Some(1));
-    assert_eq!(detail.rule_index, None);
-    let reason = detail.reason;
-    assert!(reason.contains("default"));
+    assert!(fp.shutdown(Duration::from_secs(1)).await);
+    assert_eq!(fp.connection_state(), ConnectionState::Disconnected);
+}
+
+#[cfg(feature = "sse")]
+async fn setup_sse_api(port: u16) {
+    use axum::response::sse::{Event, KeepAlive, Sse};
+    use futures_util::stream::{self, StreamExt};
+    use std::convert::Infallible;
+
+    let realtime = || async {
+        let update = serde_json::json!({
+            "version": 2,
+            "user": "some-user-key",
+            "toggles": {
+                "pushed_toggle": { "value": true, "reason": "rule 0", "trackAccessEvents": false }
+            }
+        });
+        let event = Event::default().event("update").data(update.to_string());
+        let events = stream::iter(vec![Ok::<_, Infallible>(event)]).chain(stream::pending());
+        Sse::new(events).keep_alive(KeepAlive::default())
+    };
+    let toggles = || async {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/fixtures/toggles.json");
+        fs::read_to_string(path).unwrap()
+    };
+    let app = Router::new()
+        .route("/api/client-sdk/toggles", get(toggles))
+        .route("/realtime", get(realtime))
+        .route("/api/events", axum::routing::post(|| async {}));
+    let addr = SocketAddr::from(([0, 0, 0, 0], port));
+    tokio::spawn(async move {
+        let _ = axum::Server::bind(&addr)
+            .serve(app.into_make_service())
+            .await;
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
 }
 
 async fn setup_mock_api(port: u16) {
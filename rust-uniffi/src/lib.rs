@@ -1,12 +1,18 @@
+use feature_probe_mobile_sdk_core::DataSourceMode as CoreDataSourceMode;
 use feature_probe_mobile_sdk_core::FPConfig as CoreFPConfig;
 use feature_probe_mobile_sdk_core::FPDetail;
+use feature_probe_mobile_sdk_core::FPError as CoreFPError;
+use feature_probe_mobile_sdk_core::FPState as CoreFPState;
 use feature_probe_mobile_sdk_core::FPUser as CoreFPUser;
 use feature_probe_mobile_sdk_core::FeatureProbe as CoreFeatureProbe;
+use feature_probe_mobile_sdk_core::RealtimeTransport as CoreRealtimeTransport;
+use feature_probe_mobile_sdk_core::Reason as CoreReason;
 use feature_probe_mobile_sdk_core::Url;
 use lazy_static::lazy_static;
 use parking_lot::Mutex;
 use serde::Serialize;
 use serde_json::Value;
+use std::path::PathBuf;
 use std::time::Duration;
 use std::time::SystemTime;
 use std::time::UNIX_EPOCH;
@@ -22,6 +28,15 @@ lazy_static! {
         .expect("can not start tokio runtime");
 }
 
+// Runtime::block_on panics on a runtime thread, e.g. when called from a
+// toggle listener, block in place there instead.
+fn block_on<F: std::future::Future>(future: F) -> F::Output {
+    match tokio::runtime::Handle::try_current() {
+        Ok(handle) => tokio::task::block_in_place(|| handle.block_on(future)),
+        Err(_) => TOKIO_RUNTIME.block_on(future),
+    }
+}
+
 struct FeatureProbe {
     core: CoreFeatureProbe,
 }
@@ -29,38 +44,76 @@ struct FeatureProbe {
 impl FeatureProbe {
     fn new(config: Arc<FPConfig>, user: Arc<FPUser>) -> Self {
         let _enter = TOKIO_RUNTIME.enter();
-        let c_user = CoreFPUser::new(user.key.lock().clone())
-            .with_attrs(user.attrs.lock().clone().into_iter());
-
-        let c_config = CoreFPConfig {
-            toggles_url: config.remote_url.toggles_url.clone(),
-            events_url: config.remote_url.events_url.clone(),
-            realtime_url: config.remote_url.realtime_url.clone(),
-            client_sdk_key: config.client_sdk_key.clone(),
-            start_wait: Some(Duration::from_secs(config.start_wait as u64)),
-            refresh_interval: Duration::from_secs(config.refresh_interval as u64),
-        };
-
-        let core = CoreFeatureProbe::new(c_config, c_user);
+        let core = CoreFeatureProbe::new(config.to_core(), user.to_core());
         FeatureProbe { core }
     }
 
+    fn try_new(config: Arc<FPConfig>, user: Arc<FPUser>) -> Result<Self, FPError> {
+        let _enter = TOKIO_RUNTIME.enter();
+        let core = CoreFeatureProbe::try_new(config.to_core(), user.to_core())?;
+        Ok(FeatureProbe { core })
+    }
+
+    fn state(&self) -> FPState {
+        self.core.state().into()
+    }
+
+    fn wait_until_ready(&self, timeout: u32) -> Result<(), FPError> {
+        let timeout = Duration::from_secs(timeout as u64);
+        Ok(block_on(self.core.wait_until_ready(timeout))?)
+    }
+
     fn close(&self) {
+        let _enter = TOKIO_RUNTIME.enter();
         self.core.close()
     }
 
+    fn pause(&self) {
+        let _enter = TOKIO_RUNTIME.enter();
+        self.core.pause()
+    }
+
+    fn resume(&self) {
+        let _enter = TOKIO_RUNTIME.enter();
+        self.core.resume()
+    }
+
+    fn shutdown(&self, timeout: u32) -> bool {
+        let timeout = Duration::from_secs(timeout as u64);
+        block_on(self.core.shutdown(timeout))
+    }
+
+    fn refresh(&self) -> Result<bool, FPError> {
+        Ok(block_on(self.core.refresh())?)
+    }
+
+    fn identify(&self, user: Arc<FPUser>, timeout: u32) -> Result<(), FPError> {
+        let c_user = user.to_core();
+        if timeout == 0 {
+            let _enter = TOKIO_RUNTIME.enter();
+            self.core.identify(c_user);
+            return Ok(());
+        }
+        let timeout = Duration::from_secs(timeout as u64);
+        Ok(block_on(self.core.identify_and_wait(c_user, timeout))?)
+    }
+
+    fn add_user(&self, user: Arc<FPUser>) -> Arc<FeatureProbe> {
+        let _enter = TOKIO_RUNTIME.enter();
+        let core = self.core.add_user(user.to_core());
+        Arc::new(FeatureProbe { core })
+    }
+
+    fn remove_user(&self, key: &str) -> bool {
+        self.core.remove_user(key)
+    }
+
     fn bool_value(&self, toggle: &str, default_value: bool) -> bool {
         self.core.bool_value(toggle, default_value)
     }
 
     fn bool_detail(&self, toggle: &str, default_value: bool) -> FPBoolDetail {
-        let d = self.core.bool_detail(toggle, default_value);
-        FPBoolDetail {
-            value: d.value,
-            rule_index: d.rule_index.map(|f| f as u16),
-            version: d.version,
-            reason: d.reason,
-        }
+        self.core.bool_detail(toggle, default_value).into()
     }
 
     fn number_value(&self, toggle: &str, default_value: f64) -> f64 {
@@ -68,13 +121,15 @@ impl FeatureProbe {
     }
 
     fn number_detail(&self, toggle: &str, default_value: f64) -> FPNumDetail {
-        let d = self.core.number_detail(toggle, default_value);
-        FPNumDetail {
-            value: d.value,
-            rule_index: d.rule_index.map(|f| f as u16),
-            version: d.version,
-            reason: d.reason,
-        }
+        self.core.number_detail(toggle, default_value).into()
+    }
+
+    fn int_value(&self, toggle: &str, default_value: i64) -> i64 {
+        self.core.int_value(toggle, default_value)
+    }
+
+    fn int_detail(&self, toggle: &str, default_value: i64) -> FPIntDetail {
+        self.core.int_detail(toggle, default_value).into()
     }
 
     fn string_value(&self, toggle: &str, default_value: String) -> String {
@@ -82,42 +137,94 @@ impl FeatureProbe {
     }
 
     fn string_detail(&self, toggle: &str, default_value: String) -> FPStrDetail {
-        let d = self.core.string_detail(toggle, default_value);
-        FPStrDetail {
-            value: d.value,
-            rule_index: d.rule_index.map(|f| f as u16),
-            version: d.version,
-            reason: d.reason,
-        }
+        self.core.string_detail(toggle, default_value).into()
     }
 
-    fn json_value(&self, toggle: &str, default_value: String) -> String {
-        let default_value =
-            serde_json::from_str(&default_value).expect("default_value is not json");
-        let v = self.core.json_value(toggle, default_value);
-        serde_json::to_string(&v).expect("invalid json")
+    fn json_value(&self, toggle: &str, default_value: String) -> Result<String, FPError> {
+        let default_value = serde_json::from_str(&default_value)?;
+        Ok(self.core.json_value(toggle, default_value).to_string())
     }
 
-    fn json_detail(&self, toggle: &str, default_value: String) -> FPJsonDetail {
-        let default_value =
-            serde_json::from_str(&default_value).expect("default_value is not json");
-        let d = self.core.json_detail(toggle, default_value);
-        let value = serde_json::to_string(&d.value).expect("invalid json");
-        FPJsonDetail {
-            value,
-            rule_index: d.rule_index.map(|f| f as u16),
-            version: d.version,
-            reason: d.reason,
-        }
+    fn json_detail(&self, toggle: &str, default_value: String) -> Result<FPJsonDetail, FPError> {
+        let default_value = serde_json::from_str(&default_value)?;
+        Ok(self.core.json_detail(toggle, default_value).into())
+    }
+
+    fn peek_bool_detail(&self, toggle: &str, default_value: bool) -> FPBoolDetail {
+        self.core.peek_bool_detail(toggle, default_value).into()
+    }
+
+    fn peek_number_detail(&self, toggle: &str, default_value: f64) -> FPNumDetail {
+        self.core.peek_number_detail(toggle, default_value).into()
+    }
+
+    fn peek_int_detail(&self, toggle: &str, default_value: i64) -> FPIntDetail {
+        self.core.peek_int_detail(toggle, default_value).into()
+    }
+
+    fn peek_string_detail(&self, toggle: &str, default_value: String) -> FPStrDetail {
+        self.core.peek_string_detail(toggle, default_value).into()
+    }
+
+    fn peek_json_detail(
+        &self,
+        toggle: &str,
+        default_value: String,
+    ) -> Result<FPJsonDetail, FPError> {
+        let default_value = serde_json::from_str(&default_value)?;
+        Ok(self.core.peek_json_detail(toggle, default_value).into())
+    }
+
+    // sorted by key, so debug screens list toggles in a stable order
+    fn all_details(&self, record_events: bool) -> Vec<FPToggleDetail> {
+        let mut all: Vec<FPToggleDetail> = self
+            .core
+            .all_details(record_events)
+            .into_iter()
+            .map(|(key, d)| FPToggleDetail {
+                key,
+                detail: d.into(),
+            })
+            .collect();
+        all.sort_by(|a, b| a.key.cmp(&b.key));
+        all
     }
 
     fn track(&self, event: &str, value: Option<f64>) {
         self.core.track_event(event, value);
     }
 
-    fn new_for_test(toggles: String) -> Self {
-        let m: HashMap<String, Value> =
-            serde_json::from_str(&toggles).expect("invalid default toggles json");
+    fn subscribe(&self, key: Option<String>, listener: Box<dyn FPToggleListener>) -> u64 {
+        self.core.subscribe(key.as_deref(), move |c| {
+            listener.on_change(
+                c.key.clone(),
+                c.old.clone().map(FPJsonDetail::from),
+                c.new.clone().map(FPJsonDetail::from),
+            )
+        })
+    }
+
+    fn unsubscribe(&self, id: u64) -> bool {
+        self.core.unsubscribe(id)
+    }
+
+    // value is a json string, e.g. `true`, `"text"` or `{"k":1}`
+    fn set_override(&self, key: &str, value: &str) -> Result<(), FPError> {
+        let value: Value = serde_json::from_str(value)?;
+        self.core.set_override(key, value);
+        Ok(())
+    }
+
+    fn clear_override(&self, key: &str) -> bool {
+        self.core.clear_override(key)
+    }
+
+    fn clear_all_overrides(&self) {
+        self.core.clear_all_overrides()
+    }
+
+    fn new_for_test(toggles: String) -> Result<Self, FPError> {
+        let m: HashMap<String, Value> = serde_json::from_str(&toggles)?;
 
         let repo: HashMap<String, FPDetail<Value>> = m
             .into_iter()
@@ -133,40 +240,313 @@ impl FeatureProbe {
             .collect();
 
         let core = CoreFeatureProbe::new_with(repo);
-        FeatureProbe { core }
+        Ok(FeatureProbe { core })
     }
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub struct FPBoolDetail {
     pub value: bool,
-    pub rule_index: Option<u16>,
+    pub rule_index: Option<u64>,
+    pub variation_index: Option<u64>,
     pub version: Option<u64>,
     pub reason: String,
+    pub reason_kind: FPReason,
+    pub track_access_events: bool,
+    pub debug_until_time: Option<u64>,
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub struct FPNumDetail {
     pub value: f64,
-    pub rule_index: Option<u16>,
+    pub rule_index: Option<u64>,
+    pub variation_index: Option<u64>,
     pub version: Option<u64>,
     pub reason: String,
+    pub reason_kind: FPReason,
+    pub track_access_events: bool,
+    pub debug_until_time: Option<u64>,
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug)]
+pub struct FPIntDetail {
+    pub value: i64,
+    pub rule_index: Option<u64>,
+    pub variation_index: Option<u64>,
+    pub version: Option<u64>,
+    pub reason: String,
+    pub reason_kind: FPReason,
+    pub track_access_events: bool,
+    pub debug_until_time: Option<u64>,
+}
+
+#[derive(Debug)]
 pub struct FPStrDetail {
     pub value: String,
-    pub rule_index: Option<u16>,
+    pub rule_index: Option<u64>,
+    pub variation_index: Option<u64>,
     pub version: Option<u64>,
     pub reason: String,
+    pub reason_kind: FPReason,
+    pub track_access_events: bool,
+    pub debug_until_time: Option<u64>,
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub struct FPJsonDetail {
     pub value: String,
-    pub rule_index: Option<u16>,
+    pub rule_index: Option<u64>,
+    pub variation_index: Option<u64>,
     pub version: Option<u64>,
     pub reason: String,
+    pub reason_kind: FPReason,
+    pub track_access_events: bool,
+    pub debug_until_time: Option<u64>,
+}
+
+#[derive(Debug)]
+pub struct FPToggleDetail {
+    pub key: String,
+    pub detail: FPJsonDetail,
+}
+
+impl From<FPDetail<bool>> for FPBoolDetail {
+    fn from(d: FPDetail<bool>) -> Self {
+        let reason_kind = d.reason_kind().into();
+        FPBoolDetail {
+            value: d.value,
+            rule_index: d.rule_index.map(|i| i as u64),
+            variation_index: d.variation_index.map(|i| i as u64),
+            version: d.version,
+            reason_kind,
+            reason: d.reason,
+            track_access_events: d.track_access_events,
+            debug_until_time: d.debug_until_time.and_then(|t| u64::try_from(t).ok()),
+        }
+    }
+}
+
+impl From<FPDetail<f64>> for FPNumDetail {
+    fn from(d: FPDetail<f64>) -> Self {
+        let reason_kind = d.reason_kind().into();
+        FPNumDetail {
+            value: d.value,
+            rule_index: d.rule_index.map(|i| i as u64),
+            variation_index: d.variation_index.map(|i| i as u64),
+            version: d.version,
+            reason_kind,
+            reason: d.reason,
+            track_access_events: d.track_access_events,
+            debug_until_time: d.debug_until_time.and_then(|t| u64::try_from(t).ok()),
+        }
+    }
+}
+
+impl From<FPDetail<i64>> for FPIntDetail {
+    fn from(d: FPDetail<i64>) -> Self {
+        let reason_kind = d.reason_kind().into();
+        FPIntDetail {
+            value: d.value,
+            rule_index: d.rule_index.map(|i| i as u64),
+            variation_index: d.variation_index.map(|i| i as u64),
+            version: d.version,
+            reason_kind,
+            reason: d.reason,
+            track_access_events: d.track_access_events,
+            debug_until_time: d.debug_until_time.and_then(|t| u64::try_from(t).ok()),
+        }
+    }
+}
+
+impl From<FPDetail<String>> for FPStrDetail {
+    fn from(d: FPDetail<String>) -> Self {
+        let reason_kind = d.reason_kind().into();
+        FPStrDetail {
+            value: d.value,
+            rule_index: d.rule_index.map(|i| i as u64),
+            variation_index: d.variation_index.map(|i| i as u64),
+            version: d.version,
+            reason_kind,
+            reason: d.reason,
+            track_access_events: d.track_access_events,
+            debug_until_time: d.debug_until_time.and_then(|t| u64::try_from(t).ok()),
+        }
+    }
+}
+
+impl From<FPDetail<Value>> for FPJsonDetail {
+    fn from(d: FPDetail<Value>) -> Self {
+        let reason_kind = d.reason_kind().into();
+        let value = d.value.to_string();
+        FPJsonDetail {
+            value,
+            rule_index: d.rule_index.map(|i| i as u64),
+            variation_index: d.variation_index.map(|i| i as u64),
+            version: d.version,
+            reason_kind,
+            reason: d.reason,
+            track_access_events: d.track_access_events,
+            debug_until_time: d.debug_until_time.and_then(|t| u64::try_from(t).ok()),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum FPReason {
+    RuleMatch { index: u64 },
+    Default,
+    Disabled,
+    NotFound,
+    TypeMismatch,
+    Override,
+    Cached,
+    NotReady,
+    Unknown,
+}
+
+impl From<CoreReason> for FPReason {
+    fn from(r: CoreReason) -> Self {
+        match r {
+            CoreReason::RuleMatch { index } => FPReason::RuleMatch {
+                index: index as u64,
+            },
+            CoreReason::Default => FPReason::Default,
+            CoreReason::Disabled => FPReason::Disabled,
+            CoreReason::NotFound => FPReason::NotFound,
+            CoreReason::TypeMismatch => FPReason::TypeMismatch,
+            CoreReason::Override => FPReason::Override,
+            CoreReason::Cached => FPReason::Cached,
+            CoreReason::NotReady => FPReason::NotReady,
+            _ => FPReason::Unknown,
+        }
+    }
+}
+
+pub trait FPToggleListener: Send + Sync + std::fmt::Debug {
+    fn on_change(
+        &self,
+        key: String,
+        old_detail: Option<FPJsonDetail>,
+        new_detail: Option<FPJsonDetail>,
+    );
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum FPError {
+    #[error("invalid json: {message}")]
+    JsonError { message: String },
+    #[error("invalid http: {message}")]
+    HttpError { message: String },
+    #[error("invalid url: {message}")]
+    UrlError { message: String },
+    #[error("cache error: {message}")]
+    CacheError { message: String },
+    #[error("unauthorized: status code {status}")]
+    Unauthorized { status: u16 },
+    #[error("request timeout")]
+    Timeout,
+    #[error("client error: status code {status}")]
+    ClientError { status: u16 },
+    #[error("server error: status code {status}")]
+    ServerError {
+        status: u16,
+        retry_after_secs: Option<u64>,
+    },
+    #[error("rate limited, retry after {retry_after_secs:?} seconds")]
+    RateLimited { retry_after_secs: Option<u64> },
+    #[error("invalid payload: {message}")]
+    InvalidPayload { message: String },
+}
+
+impl From<serde_json::Error> for FPError {
+    fn from(e: serde_json::Error) -> Self {
+        FPError::JsonError {
+            message: e.to_string(),
+        }
+    }
+}
+
+impl From<CoreFPError> for FPError {
+    fn from(e: CoreFPError) -> Self {
+        match e {
+            CoreFPError::JsonError(message) => FPError::JsonError { message },
+            CoreFPError::HttpError(message) => FPError::HttpError { message },
+            CoreFPError::UrlError(message) => FPError::UrlError { message },
+            CoreFPError::CacheError(message) => FPError::CacheError { message },
+            CoreFPError::Unauthorized { status } => FPError::Unauthorized { status },
+            CoreFPError::Timeout => FPError::Timeout,
+            CoreFPError::ClientError { status } => FPError::ClientError { status },
+            CoreFPError::ServerError {
+                status,
+                retry_after,
+            } => FPError::ServerError {
+                status,
+                retry_after_secs: retry_after.map(|d| d.as_secs()),
+            },
+            CoreFPError::RateLimited { retry_after } => FPError::RateLimited {
+                retry_after_secs: retry_after.map(|d| d.as_secs()),
+            },
+            CoreFPError::InvalidPayload(message) => FPError::InvalidPayload { message },
+            e => FPError::HttpError {
+                message: e.to_string(),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FPState {
+    Initializing,
+    Ready,
+    Stale,
+    Failed,
+}
+
+impl From<CoreFPState> for FPState {
+    fn from(s: CoreFPState) -> Self {
+        match s {
+            CoreFPState::Initializing => FPState::Initializing,
+            CoreFPState::Ready => FPState::Ready,
+            CoreFPState::Stale => FPState::Stale,
+            CoreFPState::Failed => FPState::Failed,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FPDataSourceMode {
+    Polling,
+    Realtime,
+    RealtimeWithPollingFallback,
+    Manual,
+}
+
+impl From<FPDataSourceMode> for CoreDataSourceMode {
+    fn from(m: FPDataSourceMode) -> Self {
+        match m {
+            FPDataSourceMode::Polling => CoreDataSourceMode::Polling,
+            FPDataSourceMode::Realtime => CoreDataSourceMode::Realtime,
+            FPDataSourceMode::RealtimeWithPollingFallback => {
+                CoreDataSourceMode::RealtimeWithPollingFallback
+            }
+            FPDataSourceMode::Manual => CoreDataSourceMode::Manual,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FPRealtimeTransport {
+    SocketIo,
+    Sse,
+}
+
+impl From<FPRealtimeTransport> for CoreRealtimeTransport {
+    fn from(t: FPRealtimeTransport) -> Self {
+        match t {
+            FPRealtimeTransport::SocketIo => CoreRealtimeTransport::SocketIo,
+            FPRealtimeTransport::Sse => CoreRealtimeTransport::Sse,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -241,20 +621,70 @@ pub struct FPConfig {
     pub client_sdk_key: String,
     pub refresh_interval: u32,
     pub start_wait: u32,
+    pub cache_dir: Option<String>,
+    pub dedup_access_events: bool,
+    pub events_flush_interval: Option<u32>,
+    pub events_capacity: u32,
+    pub http_timeout: Option<u32>,
+    pub data_source: Option<FPDataSourceMode>,
+    pub realtime_transport: Option<FPRealtimeTransport>,
 }
 
 impl FPConfig {
+    // arguments of the FPConfig constructor in featureprobe.udl
+    #[allow(clippy::too_many_arguments)]
     fn new(
         remote_url: Arc<FPUrl>,
         client_sdk_key: String,
         refresh_interval: u32,
         start_wait: u32,
+        cache_dir: Option<String>,
+        dedup_access_events: bool,
+        events_flush_interval: Option<u32>,
+        events_capacity: u32,
+        http_timeout: Option<u32>,
+        data_source: Option<FPDataSourceMode>,
+        realtime_transport: Option<FPRealtimeTransport>,
     ) -> Self {
         FPConfig {
             remote_url,
             client_sdk_key,
             refresh_interval,
             start_wait,
+            cache_dir,
+            dedup_access_events,
+            events_flush_interval,
+            events_capacity,
+            http_timeout,
+            data_source,
+            realtime_transport,
+        }
+    }
+
+    fn to_core(&self) -> CoreFPConfig {
+        CoreFPConfig {
+            toggles_url: self.remote_url.toggles_url.clone(),
+            events_url: self.remote_url.events_url.clone(),
+            realtime_url: self.remote_url.realtime_url.clone(),
+            client_sdk_key: self.client_sdk_key.clone(),
+            start_wait: Some(Duration::from_secs(self.start_wait as u64)),
+            refresh_interval: Duration::from_secs(self.refresh_interval as u64),
+            // flush interval and http timeout used to be the refresh interval
+            // a zero interval is not a valid flush period
+            events_flush_interval: Duration::from_secs(
+                self.events_flush_interval
+                    .unwrap_or(self.refresh_interval)
+                    .max(1) as u64,
+            ),
+            events_capacity: self.events_capacity as usize,
+            http_timeout: Duration::from_secs(
+                self.http_timeout.unwrap_or(self.refresh_interval) as u64
+            ),
+            cache_dir: self.cache_dir.as_ref().map(PathBuf::from),
+            retry_policy: Default::default(),
+            dedup_access_events: self.dedup_access_events,
+            data_source: self.data_source.map(Into::into).unwrap_or_default(),
+            realtime_transport: self.realtime_transport.map(Into::into).unwrap_or_default(),
         }
     }
 }
@@ -282,14 +712,108 @@ impl FPUser {
         let mut guard = self.key.lock();
         *guard = key;
     }
+
+    fn to_core(&self) -> CoreFPUser {
+        CoreFPUser::new(self.key.lock().clone()).with_attrs(self.attrs.lock().clone().into_iter())
+    }
 }
 
 fn generate_key() -> String {
     let start = SystemTime::now();
-    let since_the_epoch = start
-        .duration_since(UNIX_EPOCH)
-        .expect("Time went before epoch");
+    let since_the_epoch = start.duration_since(UNIX_EPOCH).unwrap_or_default();
     format!("{}", since_the_epoch.as_micros())
 }
 
-uniffi_macros::include_scaffolding!("featureprobe");
+// lints triggered by the generated scaffolding
+#[allow(clippy::unneeded_struct_pattern, clippy::useless_conversion)]
+mod scaffolding {
+    use super::*;
+
+    uniffi_macros::include_scaffolding!("featureprobe");
+}
+
+pub use scaffolding::uniffi_reexport_hack;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    // answers every request with the same toggles
+    fn serve_toggles(port: u16) {
+        let listener = TcpListener::bind(("127.0.0.1", port)).unwrap();
+        std::thread::spawn(move || {
+            let body = r#"{"t":{"value":true,"reason":"rule 0","trackAccessEvents":true}}"#;
+            for mut stream in listener.incoming().flatten() {
+                let mut buf = [0; 4096];
+                let _ = stream.read(&mut buf);
+                let resp = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                let _ = stream.write_all(resp.as_bytes());
+            }
+        });
+    }
+
+    #[test]
+    fn test_evaluate_outside_runtime() {
+        let port = 19028;
+        serve_toggles(port);
+        let url = FPUrlBuilder::new(format!("http://127.0.0.1:{}", port))
+            .build()
+            .unwrap();
+        let config = FPConfig::new(
+            url,
+            "client-sdk-key".to_owned(),
+            60,
+            3,
+            None,
+            false,
+            None,
+            100,
+            None,
+            Some(FPDataSourceMode::Polling),
+            None,
+        );
+        let fp = Arc::new(FeatureProbe::new(Arc::new(config), Arc::new(FPUser::new())));
+
+        // e.g. the UI thread of the app, not a runtime thread
+        let handle = fp.clone();
+        let result = std::thread::spawn(move || {
+            let value = handle.bool_value("t", false);
+            let detail = handle.bool_detail("t", false);
+            let all = handle.all_details(true);
+            (value, detail.value, all.len())
+        })
+        .join();
+        assert_eq!(result.ok(), Some((true, true, 1)));
+        fp.close();
+    }
+
+    #[test]
+    fn test_identify_error() {
+        // nothing listens on this port
+        let url = FPUrlBuilder::new("http://127.0.0.1:19030".to_owned())
+            .build()
+            .unwrap();
+        let config = FPConfig::new(
+            url,
+            "client-sdk-key".to_owned(),
+            60,
+            0,
+            None,
+            false,
+            None,
+            100,
+            Some(1),
+            Some(FPDataSourceMode::Manual),
+            None,
+        );
+        let fp = FeatureProbe::new(Arc::new(config), Arc::new(FPUser::new()));
+        assert!(fp.identify(Arc::new(FPUser::new()), 1).is_err());
+        fp.close();
+    }
+}
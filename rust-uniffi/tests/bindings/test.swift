@@ -14,7 +14,7 @@ let toggle = fp.boolDetail(key: "campaign_enable", defaultValue: true)
 print("toogle value is \(toggle)")
 fp.close()
 
-let fp2 = FeatureProbe.newForTest(toggles: "{ \"toggle_1\": true }")
+let fp2 = try! FeatureProbe.newForTest(toggles: "{ \"toggle_1\": true }")
 let is_true = fp2.boolValue(key: "toggle_1", defaultValue: false)
 assert(is_true == true);
 
@@ -21,8 +21,8 @@ public final class OcFeatureProbe: NSObject {
         fp = FeatureProbe(config: config, user: user)
     }
 
-    @objc public init(testJson: String) {
-        fp = FeatureProbe.newForTest(toggles: testJson)
+    @objc public init(testJson: String) throws {
+        fp = try FeatureProbe.newForTest(toggles: testJson)
     }
 
     @objc public func boolValue(key: String, defaultValue: Bool) -> Bool {
@@ -43,6 +43,15 @@ public final class OcFeatureProbe: NSObject {
         return OFpNumberDetail(detail: d)
     }
 
+    @objc public func intValue(key: String, defaultValue: Int64) -> Int64 {
+        fp.intValue(key: key, defaultValue: defaultValue)
+    }
+
+    @objc public func intDetail(key: String, defaultValue: Int64) -> OFpIntDetail {
+        let d = fp.intDetail(key: key, defaultValue: defaultValue)
+        return OFpIntDetail(detail: d)
+    }
+
     @objc public func stringValue(key: String, defaultValue: String) -> String {
         fp.stringValue(key: key, defaultValue: defaultValue)
     }
@@ -52,12 +61,12 @@ public final class OcFeatureProbe: NSObject {
         return OFpStringDetail(detail: d)
     }
 
-    @objc public func jsonValue(key: String, defaultValue: String) -> String {
-        fp.jsonValue(key: key, defaultValue: defaultValue)
+    @objc public func jsonValue(key: String, defaultValue: String) throws -> String {
+        try fp.jsonValue(key: key, defaultValue: defaultValue)
     }
 
-    @objc public func jsonDetail(key: String, defaultValue: String) -> OFpJsonDetail {
-        let d = fp.jsonDetail(key: key, defaultValue: defaultValue)
+    @objc public func jsonDetail(key: String, defaultValue: String) throws -> OFpJsonDetail {
+        let d = try fp.jsonDetail(key: key, defaultValue: defaultValue)
         return OFpJsonDetail(detail: d)
     }
 
@@ -73,6 +82,24 @@ public final class OcFeatureProbe: NSObject {
         fp.close()
     }
 
+    @objc public func pause() {
+        fp.pause()
+    }
+
+    @objc public func resume() {
+        fp.resume()
+    }
+
+    @objc public func refresh(completion: @escaping (Bool, Error?) -> Void) {
+        DispatchQueue.global().async {
+            do {
+                completion(try self.fp.refresh(), nil)
+            } catch {
+                completion(false, error)
+            }
+        }
+    }
+
 }
 
 @objc(FpUser)
@@ -147,6 +174,18 @@ public final class OFpBoolDetail: NSObject {
         }
     }
 
+    @objc public var variationIndex: NSNumber {
+        if _detail.variationIndex == nil {
+            return -1
+        } else {
+            return _detail.variationIndex! as NSNumber
+        }
+    }
+
+    @objc public var trackAccessEvents: Bool {
+        _detail.trackAccessEvents
+    }
+
     @objc public var reason: String {
         _detail.reason
     }
@@ -180,6 +219,63 @@ public final class OFpNumberDetail: NSObject {
         }
     }
 
+    @objc public var variationIndex: NSNumber {
+        if _detail.variationIndex == nil {
+            return -1
+        } else {
+            return _detail.variationIndex! as NSNumber
+        }
+    }
+
+    @objc public var trackAccessEvents: Bool {
+        _detail.trackAccessEvents
+    }
+
+    @objc public var reason: String {
+        _detail.reason
+    }
+}
+
+@objc(FpIntDetail)
+public final class OFpIntDetail: NSObject {
+    var _detail: FpIntDetail
+
+    public init(detail: FpIntDetail) {
+        _detail = detail
+    }
+
+    @objc public var value: Int64 {
+        _detail.value
+    }
+
+    @objc public var ruleIndex: NSNumber {
+        if _detail.ruleIndex == nil {
+            return -1
+        } else {
+            return _detail.ruleIndex! as NSNumber
+        }
+    }
+
+    @objc public var version: NSNumber {
+        if _detail.version == nil {
+            return -1
+        } else {
+            return _detail.version! as NSNumber
+        }
+    }
+
+    @objc public var variationIndex: NSNumber {
+        if _detail.variationIndex == nil {
+            return -1
+        } else {
+            return _detail.variationIndex! as NSNumber
+        }
+    }
+
+    @objc public var trackAccessEvents: Bool {
+        _detail.trackAccessEvents
+    }
+
     @objc public var reason: String {
         _detail.reason
     }
@@ -213,6 +309,18 @@ public final class OFpStringDetail: NSObject {
         }
     }
 
+    @objc public var variationIndex: NSNumber {
+        if _detail.variationIndex == nil {
+            return -1
+        } else {
+            return _detail.variationIndex! as NSNumber
+        }
+    }
+
+    @objc public var trackAccessEvents: Bool {
+        _detail.trackAccessEvents
+    }
+
     @objc public var reason: String {
         _detail.reason
     }
@@ -246,6 +354,18 @@ public final class OFpJsonDetail: NSObject {
         }
     }
 
+    @objc public var variationIndex: NSNumber {
+        if _detail.variationIndex == nil {
+            return -1
+        } else {
+            return _detail.variationIndex! as NSNumber
+        }
+    }
+
+    @objc public var trackAccessEvents: Bool {
+        _detail.trackAccessEvents
+    }
+
     @objc public var reason: String {
         _detail.reason
     }